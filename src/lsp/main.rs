@@ -0,0 +1,435 @@
+//! Language server for EvilStack, speaking the Language Server Protocol
+//! over stdio. Documents are kept in full and re-tokenized on every request.
+
+use evilstack_lang::{
+    docs,
+    error::{Error, Severity, DEFAULT_MAX_ERRORS},
+    evilstack_vm::{EvilStackVM, MNEMONICS},
+    include,
+    tokenizer::{SemanticTokenType, Token, TokenType, Tokenizer},
+};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+};
+
+const METHOD_NOT_FOUND: i64 = -32601;
+
+// `CompletionItemKind`, `SymbolKind` and `DiagnosticSeverity` values from
+// the specification.
+const COMPLETION_KEYWORD: u32 = 14;
+const COMPLETION_REFERENCE: u32 = 18;
+const SYMBOL_FUNCTION: u32 = 12;
+const SEVERITY_ERROR: u32 = 1;
+const SEVERITY_WARNING: u32 = 2;
+
+/// Token types and modifiers advertised for semantic highlighting. Tokens
+/// refer to them by index.
+const TOKEN_TYPES: &[&str] = &["keyword", "function", "number", "string", "comment"];
+const TOKEN_MODIFIERS: &[&str] = &["declaration"];
+
+/// Returns the index into `TOKEN_TYPES` and the modifier bits for a token.
+fn semantic_token_type(token_type: SemanticTokenType) -> (u32, u32) {
+    match token_type {
+        SemanticTokenType::Instruction => (0, 0),
+        SemanticTokenType::LabelDefinition => (1, 1),
+        SemanticTokenType::LabelReference => (1, 0),
+        SemanticTokenType::Number => (2, 0),
+        SemanticTokenType::String => (3, 0),
+        SemanticTokenType::Comment => (4, 0),
+    }
+}
+
+/// Reads one message framed with a `Content-Length` header. Returns `None`
+/// at the end of the input.
+fn read_message(input: &mut impl BufRead) -> Option<Value> {
+    let mut length = None;
+
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header).ok()? == 0 {
+            return None;
+        }
+
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+
+    let mut body = vec![0; length?];
+    input.read_exact(&mut body).ok()?;
+    serde_json::from_slice(&body).ok()
+}
+
+fn send(message: &Value) {
+    let body = message.to_string();
+    let mut stdout = io::stdout().lock();
+    let _ = write!(stdout, "Content-Length: {}\r\n\r\n{}", body.len(), body);
+    let _ = stdout.flush();
+}
+
+fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokenizer = Tokenizer::new();
+    let _ = tokenizer.tokenize(text);
+    tokenizer.tokens
+}
+
+fn range(line: usize, column: usize, length: usize) -> Value {
+    let line = line.saturating_sub(1);
+    let column = column.saturating_sub(1);
+    json!({
+        "start": { "line": line, "character": column },
+        "end": { "line": line, "character": column + length },
+    })
+}
+
+fn token_range(token: &Token) -> Value {
+    range(token.span.line, token.span.column, token.span.length)
+}
+
+/// Returns the label a token defines (`name:`) or refers to (`@name`).
+fn label_name(token: &Token) -> Option<&str> {
+    if token.token_type != TokenType::Identifier {
+        return None;
+    }
+    token
+        .value
+        .strip_suffix(':')
+        .or_else(|| token.value.strip_prefix('@'))
+}
+
+fn is_definition(token: &Token) -> bool {
+    token.token_type == TokenType::Identifier && token.value.ends_with(':')
+}
+
+/// Returns the token under an LSP position, counting the position right
+/// after a token as part of it.
+fn token_at<'a>(tokens: &'a [Token], position: &Value) -> Option<&'a Token> {
+    let line = position["line"].as_u64()? as usize + 1;
+    let character = position["character"].as_u64()? as usize + 1;
+
+    tokens.iter().find(|token| {
+        token.token_type != TokenType::EOF
+            && token.token_type != TokenType::Comment
+            && token.span.line == line
+            && token.span.column <= character
+            && character <= token.span.column + token.span.length
+    })
+}
+
+fn diagnostic(error: &Error) -> Value {
+    let related: Vec<Value> = error
+        .notes
+        .iter()
+        .map(|note| {
+            let file = note.file.as_deref().unwrap_or_default();
+            json!({
+                "location": {
+                    "uri": format!("file://{}", file),
+                    "range": range(note.span.line, note.span.column, note.span.length),
+                },
+                "message": note.message,
+            })
+        })
+        .collect();
+
+    let range = match error.span {
+        Some(span) => range(span.line, span.column, span.length),
+        None => range(1, 1, 0),
+    };
+
+    let mut message = error.message.clone();
+    for help in &error.help {
+        message += &format!("\nhelp: {}", help);
+    }
+
+    json!({
+        "range": range,
+        "severity": match error.severity {
+            Severity::Error => SEVERITY_ERROR,
+            Severity::Warning => SEVERITY_WARNING,
+        },
+        "code": error.code,
+        "source": "estack",
+        "message": message,
+        "relatedInformation": related,
+    })
+}
+
+/// Collects the tokenizer and compiler errors of a document and, if it
+/// compiles, the warnings, type errors and lints. Only diagnostics in the
+/// document itself are kept, not those in the files it includes. Errors in
+/// code from a macro point at the macro use in their related information.
+fn diagnostics(uri: &str, text: &str) -> Vec<Error> {
+    let path = uri.strip_prefix("file://").unwrap_or(uri);
+    let (sources, symbols, mut errors) = include::load(path, text, DEFAULT_MAX_ERRORS);
+
+    let mut runtime = EvilStackVM::new(symbols);
+    match runtime.load() {
+        Ok(()) => {
+            errors.extend(runtime.warnings().iter().cloned());
+            errors.extend(runtime.check_types());
+            errors.extend(runtime.lint());
+        }
+        Err(compile_errors) => errors.extend(compile_errors),
+    }
+
+    errors.retain(|error| error.span.is_none_or(|span| span.file == 0));
+    errors
+        .into_iter()
+        .map(|error| sources.annotate(error))
+        .collect()
+}
+
+struct Server {
+    documents: HashMap<String, String>,
+    shutdown: bool,
+}
+
+impl Server {
+    fn publish_diagnostics(&self, uri: &str) {
+        let diagnostics: Vec<Value> = match self.documents.get(uri) {
+            Some(text) => diagnostics(uri, text).iter().map(diagnostic).collect(),
+            None => Vec::new(),
+        };
+
+        send(&json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics },
+        }));
+    }
+
+    fn document<'a>(&self, params: &'a Value) -> Option<(&'a str, Vec<Token>)> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        let text = self.documents.get(uri)?;
+        Some((uri, tokenize(text)))
+    }
+
+    fn notify(&mut self, method: &str, params: &Value) {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents.insert(uri.to_string(), text.to_string());
+                self.publish_diagnostics(uri);
+            }
+            "textDocument/didChange" => {
+                // Only full document sync is advertised, so the last change
+                // holds the whole text.
+                let changes = params["contentChanges"].as_array();
+                if let Some(text) = changes
+                    .and_then(|c| c.last())
+                    .and_then(|c| c["text"].as_str())
+                {
+                    self.documents.insert(uri.to_string(), text.to_string());
+                }
+                self.publish_diagnostics(uri);
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                self.publish_diagnostics(uri);
+            }
+            "exit" => std::process::exit(if self.shutdown { 0 } else { 1 }),
+            _ => {}
+        }
+    }
+
+    fn request(&mut self, method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "definitionProvider": true,
+                    "referencesProvider": true,
+                    "hoverProvider": true,
+                    "completionProvider": { "triggerCharacters": ["@"] },
+                    "documentSymbolProvider": true,
+                    "semanticTokensProvider": {
+                        "legend": { "tokenTypes": TOKEN_TYPES, "tokenModifiers": TOKEN_MODIFIERS },
+                        "full": true,
+                    },
+                },
+                "serverInfo": { "name": "estack-lsp", "version": env!("CARGO_PKG_VERSION") },
+            })),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            }
+            "textDocument/definition" => Ok(self.definition(params).unwrap_or(Value::Null)),
+            "textDocument/references" => Ok(self.references(params).unwrap_or(json!([]))),
+            "textDocument/hover" => Ok(self.hover(params).unwrap_or(Value::Null)),
+            "textDocument/completion" => Ok(self.completion(params)),
+            "textDocument/documentSymbol" => Ok(self.symbols(params).unwrap_or(json!([]))),
+            "textDocument/semanticTokens/full" => {
+                Ok(self.semantic_tokens(params).unwrap_or(Value::Null))
+            }
+            _ => Err((METHOD_NOT_FOUND, format!("Unknown method: {}", method))),
+        }
+    }
+
+    fn definition(&self, params: &Value) -> Option<Value> {
+        let (uri, tokens) = self.document(params)?;
+        let name = label_name(token_at(&tokens, &params["position"])?)?;
+
+        let definition = tokens
+            .iter()
+            .find(|token| is_definition(token) && label_name(token) == Some(name))?;
+
+        Some(json!({ "uri": uri, "range": token_range(definition) }))
+    }
+
+    fn references(&self, params: &Value) -> Option<Value> {
+        let (uri, tokens) = self.document(params)?;
+        let name = label_name(token_at(&tokens, &params["position"])?)?;
+        let declaration = params["context"]["includeDeclaration"]
+            .as_bool()
+            .unwrap_or(true);
+
+        let locations: Vec<Value> = tokens
+            .iter()
+            .filter(|token| label_name(token) == Some(name))
+            .filter(|token| declaration || !is_definition(token))
+            .map(|token| json!({ "uri": uri, "range": token_range(token) }))
+            .collect();
+
+        Some(json!(locations))
+    }
+
+    fn hover(&self, params: &Value) -> Option<Value> {
+        let (_, tokens) = self.document(params)?;
+        let token = token_at(&tokens, &params["position"])?;
+
+        let contents = match label_name(token) {
+            Some(name) => {
+                let definition = tokens
+                    .iter()
+                    .find(|token| is_definition(token) && label_name(token) == Some(name))?;
+                format!("`{}:` defined on line {}", name, definition.span.line)
+            }
+            None if token.token_type == TokenType::Identifier => {
+                let doc = docs::instruction(&token.value)?;
+                format!("```\n{}\n```\n{}", doc.usage, doc.description)
+            }
+            None => return None,
+        };
+
+        Some(json!({
+            "contents": { "kind": "markdown", "value": contents },
+            "range": token_range(token),
+        }))
+    }
+
+    fn completion(&self, params: &Value) -> Value {
+        let mut items: Vec<Value> = MNEMONICS
+            .iter()
+            .filter_map(|mnemonic| docs::instruction(mnemonic))
+            .map(|doc| {
+                json!({
+                    "label": doc.mnemonic,
+                    "kind": COMPLETION_KEYWORD,
+                    "detail": doc.usage,
+                    "documentation": doc.description,
+                })
+            })
+            .collect();
+
+        if let Some((_, tokens)) = self.document(params) {
+            for token in tokens.iter().filter(|token| is_definition(token)) {
+                let name = label_name(token).unwrap_or_default();
+                items.push(json!({
+                    "label": format!("@{}", name),
+                    "kind": COMPLETION_REFERENCE,
+                    "detail": format!("label defined on line {}", token.span.line),
+                }));
+            }
+        }
+
+        json!(items)
+    }
+
+    fn symbols(&self, params: &Value) -> Option<Value> {
+        let (_, tokens) = self.document(params)?;
+
+        let symbols: Vec<Value> = tokens
+            .iter()
+            .filter(|token| is_definition(token))
+            .map(|token| {
+                json!({
+                    "name": label_name(token).unwrap_or_default(),
+                    "kind": SYMBOL_FUNCTION,
+                    "range": token_range(token),
+                    "selectionRange": token_range(token),
+                })
+            })
+            .collect();
+
+        Some(json!(symbols))
+    }
+
+    /// Encodes the semantic tokens of a document as the relative line,
+    /// start, length, type and modifiers of each token.
+    fn semantic_tokens(&self, params: &Value) -> Option<Value> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        let text = self.documents.get(uri)?;
+
+        let mut tokenizer = Tokenizer::new();
+        let _ = tokenizer.tokenize(text);
+
+        let mut data = Vec::new();
+        let (mut line, mut column) = (1, 1);
+        for token in tokenizer.semantic_tokens(text) {
+            let span = token.span;
+            if span.line != line {
+                column = 1;
+            }
+            let (token_type, modifiers) = semantic_token_type(token.token_type);
+            data.extend([
+                (span.line - line) as u32,
+                (span.column - column) as u32,
+                span.length as u32,
+                token_type,
+                modifiers,
+            ]);
+            (line, column) = (span.line, span.column);
+        }
+
+        Some(json!({ "data": data }))
+    }
+}
+
+fn main() {
+    let mut server = Server {
+        documents: HashMap::new(),
+        shutdown: false,
+    };
+
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+
+    while let Some(message) = read_message(&mut input) {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+
+        match message.get("id") {
+            Some(id) => {
+                let response = match server.request(method, params) {
+                    Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                    Err((code, message)) => json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": { "code": code, "message": message },
+                    }),
+                };
+                send(&response);
+            }
+            None => server.notify(method, params),
+        }
+    }
+}
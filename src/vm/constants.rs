@@ -0,0 +1,186 @@
+//! Named constants, declared with `const NAME = value` or in a `.data`
+//! section that runs until `.text`, the next label or instruction, or the end
+//! of the file:
+//!
+//! ```text
+//! .data
+//!   greeting "Hello"
+//!   primes 2 3 5 7
+//! .text
+//! ```
+//!
+//! A data entry with more than one value is an array. `push NAME` pushes the
+//! value of a constant, or the elements of an array followed by their count.
+
+use crate::{
+    error::{Error, Span},
+    evilstack_vm::{ConstType, MNEMONICS},
+    tokenizer::{Symbol, SymbolType},
+};
+use std::{collections::HashMap, ops::Range};
+
+pub(crate) struct Declaration {
+    /// Index of the symbol naming the constant.
+    pub name: usize,
+    /// Indices of the symbols holding the values.
+    pub values: Range<usize>,
+}
+
+pub(crate) struct Constant {
+    pub values: Vec<ConstType>,
+    /// Position of the name in the declaration.
+    pub span: Span,
+}
+
+impl Constant {
+    pub fn is_array(&self) -> bool {
+        self.values.len() > 1
+    }
+}
+
+fn is_instruction(symbol: &Symbol, mnemonic: &str) -> bool {
+    matches!(symbol.symbol_type, SymbolType::Instruction) && symbol.value == mnemonic
+}
+
+fn is_literal(symbol: &Symbol) -> bool {
+    matches!(
+        symbol.symbol_type,
+        SymbolType::Integer | SymbolType::Float | SymbolType::String
+    )
+}
+
+fn is_name(symbol: &Symbol) -> bool {
+    matches!(symbol.symbol_type, SymbolType::Instruction)
+        && !MNEMONICS.contains(&symbol.value.as_str())
+        && !symbol.value.starts_with(['$', '.'])
+}
+
+/// Finds the constant declarations in `symbols`. Returns them together with
+/// a flag for every symbol telling whether it is part of a declaration or a
+/// section directive, and so is not code. Malformed declarations are
+/// reported in `errors` and skipped.
+pub(crate) fn declarations(
+    symbols: &[Symbol],
+    errors: &mut Vec<Error>,
+) -> (Vec<Declaration>, Vec<bool>) {
+    let mut declarations = Vec::new();
+    let mut used = vec![false; symbols.len()];
+    let mut in_data = false;
+    let mut i = 0;
+
+    while i < symbols.len() {
+        let symbol = &symbols[i];
+        if matches!(symbol.symbol_type, SymbolType::Label)
+            || MNEMONICS.contains(&symbol.value.as_str())
+            || i > 0 && symbols[i - 1].span.file != symbol.span.file
+        {
+            in_data = false;
+        }
+        let end = i + symbols[i..]
+            .iter()
+            .take_while(|other| other.span.same_line(symbol.span))
+            .count();
+
+        if is_instruction(symbol, ".data") || is_instruction(symbol, ".text") {
+            in_data = symbol.value == ".data";
+            used[i] = true;
+            i += 1;
+            continue;
+        }
+
+        let (name, values) = if is_instruction(symbol, "const") {
+            let valid = end == i + 4
+                && is_name(&symbols[i + 1])
+                && matches!(symbols[i + 2].symbol_type, SymbolType::Punctuation)
+                && is_literal(&symbols[i + 3]);
+            if !valid {
+                errors.push(
+                    Error::new("E0114", "Invalid constant declaration", symbol.span)
+                        .with_help("declare constants as `const NAME = value`"),
+                );
+            }
+            (valid.then_some(i + 1), i + 3..end)
+        } else if in_data {
+            let valid =
+                is_name(symbol) && end > i + 1 && symbols[i + 1..end].iter().all(is_literal);
+            if !valid {
+                errors.push(
+                    Error::new("E0114", "Invalid data entry", symbol.span)
+                        .with_help("data entries are a name followed by one or more values"),
+                );
+            }
+            (valid.then_some(i), i + 1..end)
+        } else {
+            i += 1;
+            continue;
+        };
+
+        used[i..end].fill(true);
+        if let Some(name) = name {
+            declarations.push(Declaration { name, values });
+        }
+        i = end;
+    }
+
+    (declarations, used)
+}
+
+/// Parses a literal symbol.
+fn literal(symbol: &Symbol) -> Result<ConstType, Error> {
+    let invalid = |kind: &str| {
+        let message = format!("Invalid {} literal: {}", kind, symbol.value);
+        Error::new("E0103", &message, symbol.span)
+    };
+
+    match symbol.symbol_type {
+        SymbolType::Integer => symbol
+            .value
+            .parse()
+            .map(ConstType::Integer)
+            .map_err(|_| invalid("integer")),
+        SymbolType::Float => symbol
+            .value
+            .parse()
+            .map(ConstType::Float)
+            .map_err(|_| invalid("float")),
+        _ => Ok(ConstType::String(symbol.value.clone())),
+    }
+}
+
+/// Evaluates the declarations found by [`declarations`].
+pub(crate) fn constants(
+    symbols: &[Symbol],
+    declarations: &[Declaration],
+    errors: &mut Vec<Error>,
+) -> HashMap<String, Constant> {
+    let mut constants: HashMap<String, Constant> = HashMap::new();
+
+    for declaration in declarations {
+        let name = &symbols[declaration.name];
+        if let Some(previous) = constants.get(&name.value) {
+            let message = format!("Constant `{}` is already defined", name.value);
+            let error = Error::new("E0115", &message, name.span)
+                .with_note("previously defined here", previous.span);
+            errors.push(error);
+            continue;
+        }
+
+        let mut values = Vec::new();
+        for symbol in &symbols[declaration.values.clone()] {
+            match literal(symbol) {
+                Ok(value) => values.push(value),
+                Err(error) => errors.push(error),
+            }
+        }
+
+        constants.insert(
+            name.value.clone(),
+            Constant {
+                values,
+                span: name.span,
+            },
+        );
+    }
+
+    constants
+}
@@ -0,0 +1,189 @@
+//! Code coverage, recorded by `estack-vm coverage`.
+//!
+//! Every compiled instruction counts how often it ran, and every conditional
+//! jump how often it was taken and not taken. Labels are not code, and
+//! neither is the `exit` added after a program that includes modules, so
+//! they are left out. An instruction that fails with a runtime error has not
+//! run, except `throw`, whose purpose is to raise one. The counts are
+//! reported per source line, as an annotated listing in the style of gcov or
+//! as an lcov tracefile.
+
+use crate::{error::SourceMap, evilstack_vm::Instruction};
+use std::collections::BTreeMap;
+
+/// What the coverage of a compiled instruction is reported as.
+#[derive(Debug, Clone, Copy)]
+struct Site {
+    file: usize,
+    line: usize,
+    is_branch: bool,
+}
+
+#[derive(Default)]
+pub struct Coverage {
+    /// Reported instructions, by their index in the program.
+    sites: Vec<Option<Site>>,
+    hits: Vec<u64>,
+    taken: Vec<u64>,
+}
+
+/// The coverage of a source line.
+#[derive(Default)]
+struct Line {
+    /// Runs of the most executed instruction on the line.
+    hits: u64,
+    /// Times taken and not taken of every conditional jump on the line.
+    branches: Vec<(u64, u64)>,
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage::default()
+    }
+
+    /// Prepares the counters for `program`. Counts recorded for the same
+    /// program before are kept, so runs of several tests add up.
+    pub(crate) fn start(&mut self, program: &[Instruction]) {
+        if self.sites.len() == program.len() {
+            return;
+        }
+
+        self.sites = program
+            .iter()
+            .map(|instruction| {
+                let pos = instruction.pos();
+                let is_code = !matches!(instruction, Instruction::Label(_, _)) && pos.length > 0;
+                is_code.then_some(Site {
+                    file: pos.file,
+                    line: pos.line,
+                    is_branch: instruction.target().is_some()
+                        && !matches!(
+                            instruction,
+                            Instruction::Jump(_, _)
+                                | Instruction::Call(_, _, _, _)
+                                | Instruction::Try(_, _)
+                        ),
+                })
+            })
+            .collect();
+        self.hits = vec![0; program.len()];
+        self.taken = vec![0; program.len()];
+    }
+
+    /// Counts a run of the instruction at `ip`. `taken` tells whether a
+    /// conditional jump jumped.
+    pub(crate) fn record(&mut self, ip: usize, taken: bool) {
+        self.hits[ip] += 1;
+        if taken {
+            self.taken[ip] += 1;
+        }
+    }
+
+    /// Adds the counts of `other`, recorded for the same program.
+    pub fn merge(&mut self, other: &Coverage) {
+        if self.sites.is_empty() {
+            self.sites = other.sites.clone();
+            self.hits = vec![0; other.hits.len()];
+            self.taken = vec![0; other.taken.len()];
+        }
+        for (hits, other) in self.hits.iter_mut().zip(&other.hits) {
+            *hits += other;
+        }
+        for (taken, other) in self.taken.iter_mut().zip(&other.taken) {
+            *taken += other;
+        }
+    }
+
+    /// The coverage of every line with code, by file index and line.
+    fn lines(&self) -> BTreeMap<usize, BTreeMap<usize, Line>> {
+        let mut files: BTreeMap<usize, BTreeMap<usize, Line>> = BTreeMap::new();
+        for (ip, site) in self.sites.iter().enumerate() {
+            let Some(site) = site else {
+                continue;
+            };
+            let line = files
+                .entry(site.file)
+                .or_default()
+                .entry(site.line)
+                .or_default();
+            line.hits = line.hits.max(self.hits[ip]);
+            if site.is_branch {
+                line.branches
+                    .push((self.taken[ip], self.hits[ip] - self.taken[ip]));
+            }
+        }
+        files
+    }
+
+    /// Renders every file with code, each line prefixed with how often it
+    /// ran: `-` for lines without code and `#####` for code that never ran.
+    /// Conditional jumps are followed by how often they were taken.
+    pub fn annotated(&self, sources: &SourceMap) -> String {
+        let mut out = String::new();
+        for (file, lines) in self.lines() {
+            let Some(source) = sources.get(file) else {
+                continue;
+            };
+            out += &format!("{:>9}:{:>5}:Source:{}\n", "-", 0, source.name);
+
+            for (i, text) in source.source.lines().enumerate() {
+                let Some(line) = lines.get(&(i + 1)) else {
+                    out += &format!("{:>9}:{:>5}:{}\n", "-", i + 1, text);
+                    continue;
+                };
+
+                let hits = match line.hits {
+                    0 => "#####".to_string(),
+                    hits => hits.to_string(),
+                };
+                out += &format!("{:>9}:{:>5}:{}\n", hits, i + 1, text);
+                for (branch, (taken, not_taken)) in line.branches.iter().enumerate() {
+                    out += &match line.hits {
+                        0 => format!("branch {:>2} never executed\n", branch),
+                        _ => format!(
+                            "branch {:>2} taken {}, not taken {}\n",
+                            branch, taken, not_taken
+                        ),
+                    };
+                }
+            }
+        }
+        out
+    }
+
+    /// Renders the coverage as an lcov tracefile. Every conditional jump is
+    /// a block with the branches taken (`0`) and not taken (`1`).
+    pub fn lcov(&self, sources: &SourceMap) -> String {
+        let mut out = String::from("TN:\n");
+        for (file, lines) in self.lines() {
+            let Some(source) = sources.get(file) else {
+                continue;
+            };
+            out += &format!("SF:{}\n", source.name);
+
+            let (mut found, mut hit) = (0, 0);
+            for (line, coverage) in &lines {
+                for (block, (taken, not_taken)) in coverage.branches.iter().enumerate() {
+                    for (branch, count) in [taken, not_taken].into_iter().enumerate() {
+                        let count = match coverage.hits {
+                            0 => "-".to_string(),
+                            _ => count.to_string(),
+                        };
+                        out += &format!("BRDA:{},{},{},{}\n", line, block, branch, count);
+                    }
+                    found += 2;
+                    hit += (*taken > 0) as usize + (*not_taken > 0) as usize;
+                }
+            }
+            out += &format!("BRF:{}\nBRH:{}\n", found, hit);
+
+            for (line, coverage) in &lines {
+                out += &format!("DA:{},{}\n", line, coverage.hits);
+            }
+            let hit = lines.values().filter(|line| line.hits > 0).count();
+            out += &format!("LF:{}\nLH:{}\n", lines.len(), hit);
+            out += "end_of_record\n";
+        }
+        out
+    }
+}
@@ -0,0 +1,245 @@
+//! An interactive debugger, run by `estack-vm debug`.
+//!
+//! Commands are read from stdin, one per line, and the program's own `read`
+//! takes the next line of the same input. Every step is kept in an undo log,
+//! so the run can also go backwards with `step-back` and `reverse-continue`
+//! to find where a value that caused an error came from.
+
+use crate::{
+    error::{self, ErrorFormat, SourceMap},
+    evilstack_vm::{ConstType, EvilStackVM, Instruction},
+};
+use std::{
+    collections::BTreeSet,
+    io::{self, Write},
+};
+
+const HELP: &str = "\
+Commands:
+  step [n], s [n]               run the next instruction, or the next n
+  continue, c                   run until a breakpoint, the end or an error
+  step-back [n], sb [n]         undo the last step, or the last n
+  reverse-continue, rc          undo steps back to the previous breakpoint
+  break <line|label>, b         stop before the code on a line of the program
+  delete <line|label>, d        remove a breakpoint
+  quit, q                       stop debugging";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Paused,
+    Ended,
+    Failed,
+}
+
+struct Debugger<'a> {
+    vm: EvilStackVM,
+    sources: &'a SourceMap,
+    format: ErrorFormat,
+    /// Lines of code that stop the run, by file index and line.
+    breakpoints: BTreeSet<(usize, usize)>,
+    state: State,
+}
+
+impl Debugger<'_> {
+    /// Where the next instruction is and what is on the stack.
+    fn show(&self) {
+        let Some(instruction) = self.vm.next_instruction() else {
+            return;
+        };
+        let position = self.sources.position(instruction.pos());
+        let stack: Vec<String> = self.vm.stack().iter().map(ConstType::to_string).collect();
+        println!("{}: {}", position, instruction);
+        println!("stack: [{}]", stack.join(", "));
+    }
+
+    fn at_breakpoint(&self) -> bool {
+        self.vm.next_instruction().is_some_and(|instruction| {
+            let pos = instruction.pos();
+            self.breakpoints.contains(&(pos.file, pos.line))
+        })
+    }
+
+    /// Runs one instruction. Returns whether the run can go on.
+    fn forward(&mut self) -> bool {
+        match self.state {
+            State::Paused => {}
+            State::Ended => {
+                println!("The program has ended, `step-back` to undo its steps.");
+                return false;
+            }
+            State::Failed => {
+                println!("The program stopped with an error, `step-back` to undo its steps.");
+                return false;
+            }
+        }
+
+        match self.vm.step_forward() {
+            Ok(true) => true,
+            Ok(false) => {
+                println!("The program ended.");
+                self.state = State::Ended;
+                false
+            }
+            Err(errors) => {
+                io::stdout().flush().ok();
+                error::print_all(errors, self.sources, self.format);
+                println!("The program stopped with an error.");
+                self.state = State::Failed;
+                false
+            }
+        }
+    }
+
+    /// Undoes one step. Returns whether there was one.
+    fn back(&mut self) -> bool {
+        if !self.vm.step_back() {
+            println!("No earlier steps are recorded.");
+            return false;
+        }
+        self.state = State::Paused;
+        true
+    }
+
+    /// The line of code `target` refers to: a line of the main program or
+    /// the first instruction of a label.
+    fn line(&self, target: &str) -> Result<(usize, usize), String> {
+        let program = self.vm.program();
+        let is_code = |instruction: &Instruction| {
+            !matches!(instruction, Instruction::Label(_, _)) && instruction.pos().length > 0
+        };
+
+        if let Ok(line) = target.parse::<usize>() {
+            let has_code = program.iter().any(|instruction| {
+                let pos = instruction.pos();
+                is_code(instruction) && pos.file == 0 && pos.line == line
+            });
+            if !has_code {
+                return Err(format!("There is no code on line {}.", line));
+            }
+            return Ok((0, line));
+        }
+
+        let label = program.iter().position(
+            |instruction| matches!(instruction, Instruction::Label(name, _) if name == target),
+        );
+        let code = label.and_then(|ip| program[ip + 1..].iter().find(|next| is_code(next)));
+        match code {
+            Some(instruction) => Ok((instruction.pos().file, instruction.pos().line)),
+            None => Err(format!(
+                "There is no label `{}` with code after it.",
+                target
+            )),
+        }
+    }
+
+    /// Runs a command. Returns `false` to stop debugging.
+    fn command(&mut self, line: &str) -> bool {
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            return true;
+        };
+        let argument = words.next();
+        let count = match argument.map(str::parse::<usize>) {
+            None => Ok(1),
+            Some(Ok(count)) => Ok(count),
+            Some(Err(_)) => Err(()),
+        };
+
+        match (command, count) {
+            ("step" | "s", Ok(count)) => {
+                for _ in 0..count {
+                    if !self.forward() {
+                        break;
+                    }
+                }
+                self.show();
+            }
+            ("step-back" | "sb", Ok(count)) => {
+                for _ in 0..count {
+                    if !self.back() {
+                        break;
+                    }
+                }
+                self.show();
+            }
+            ("continue" | "c", _) => {
+                while self.forward() {
+                    if self.at_breakpoint() {
+                        println!("Breakpoint reached.");
+                        break;
+                    }
+                }
+                self.show();
+            }
+            ("reverse-continue" | "rc", _) => {
+                while self.back() {
+                    if self.at_breakpoint() {
+                        println!("Breakpoint reached.");
+                        break;
+                    }
+                }
+                self.show();
+            }
+            ("break" | "b" | "delete" | "d", _) => {
+                let Some(target) = argument else {
+                    println!("Give a line number or a label.");
+                    return true;
+                };
+                match self.line(target) {
+                    Ok(line) if command.starts_with('b') => {
+                        self.breakpoints.insert(line);
+                        println!("Breakpoint set on line {}.", line.1);
+                    }
+                    Ok(line) if self.breakpoints.remove(&line) => {
+                        println!("Breakpoint on line {} deleted.", line.1);
+                    }
+                    Ok(line) => println!("There is no breakpoint on line {}.", line.1),
+                    Err(message) => println!("{}", message),
+                }
+            }
+            ("help" | "h", _) => println!("{}", HELP),
+            ("quit" | "q", _) => return false,
+            ("step" | "s" | "step-back" | "sb", Err(())) => {
+                println!("`{}` is not a number.", argument.unwrap_or_default());
+            }
+            _ => println!("Unknown command `{}`, `help` lists the commands.", command),
+        }
+
+        true
+    }
+}
+
+/// Debugs `vm`, whose program was loaded from `sources`, until the input
+/// ends or `quit`. Steps are kept in the undo log set by
+/// [`EvilStackVM::with_history`].
+pub fn run(vm: EvilStackVM, sources: &SourceMap, format: ErrorFormat) {
+    let mut debugger = Debugger {
+        vm,
+        sources,
+        format,
+        breakpoints: BTreeSet::new(),
+        state: State::Paused,
+    };
+    if debugger.vm.next_instruction().is_none() {
+        debugger.state = State::Ended;
+    }
+
+    println!("Type `help` for the commands.");
+    debugger.show();
+    loop {
+        print!("(estack) ");
+        io::stdout().flush().ok();
+
+        let mut line = String::new();
+        match io::stdin().read_line(&mut line) {
+            Ok(0) | Err(_) => {
+                println!();
+                break;
+            }
+            Ok(_) => {}
+        }
+        if !debugger.command(&line) {
+            break;
+        }
+    }
+}
@@ -0,0 +1,72 @@
+//! Reference documentation for every instruction, shown by editor tooling.
+
+pub struct InstructionDoc {
+    pub mnemonic: &'static str,
+    /// How the instruction is written, with its arguments.
+    pub usage: &'static str,
+    pub description: &'static str,
+}
+
+impl InstructionDoc {
+    const fn new(
+        mnemonic: &'static str,
+        usage: &'static str,
+        description: &'static str,
+    ) -> InstructionDoc {
+        InstructionDoc {
+            mnemonic,
+            usage,
+            description,
+        }
+    }
+}
+
+/// Documentation for each mnemonic in [`crate::evilstack_vm::MNEMONICS`], in
+/// the same order.
+pub const INSTRUCTIONS: &[InstructionDoc] = &[
+    InstructionDoc::new("push", "push <value>", "Pushes the value, or the value of a named constant, to the stack. An array constant pushes its elements followed by their count."),
+    InstructionDoc::new("pop", "pop", "Pops the value from the stack."),
+    InstructionDoc::new("dup", "dup", "Duplicates the top value from the stack."),
+    InstructionDoc::new("swap", "swap", "Swaps the top two values from the stack."),
+    InstructionDoc::new("set", "set <name>", "Pops the top value from the stack into a variable. Variables are local to the current call, except for the global registers `r0` to `r7`."),
+    InstructionDoc::new("get", "get <name>", "Pushes the value of a variable to the stack."),
+    InstructionDoc::new("add", "add", "Adds the top two values from the stack. Two strings are concatenated."),
+    InstructionDoc::new("sub", "sub", "Subtracts the top value from the second top value."),
+    InstructionDoc::new("mul", "mul", "Multiplies the top two values from the stack."),
+    InstructionDoc::new("div", "div", "Divides the second top value by the top value."),
+    InstructionDoc::new("idiv", "idiv", "Divides the second top value by the top value and pushes the result rounded down to an integer."),
+    InstructionDoc::new("mod", "mod", "Divides the second top value by the top value and pushes the remainder."),
+    InstructionDoc::new("print", "print", "Pops the top value from the stack and prints it."),
+    InstructionDoc::new("read", "read", "Reads a line from the input and pushes it to the stack as a string."),
+    InstructionDoc::new("atoi", "atoi", "Converts the top string value from the stack to an integer."),
+    InstructionDoc::new("ftoi", "ftoi", "Converts the top float value from the stack to an integer."),
+    InstructionDoc::new("jmp", "jmp @<label>", "Jumps to the label. The address after the jump is pushed on the return stack, so `ret` comes back here."),
+    InstructionDoc::new("jeq", "jeq @<label>", "Jumps to the label if the last `cmp` or `scmp` found the top value equal to the value it was compared with."),
+    InstructionDoc::new("jne", "jne @<label>", "Jumps to the label if the last `cmp` or `scmp` found the top value not equal to the value it was compared with."),
+    InstructionDoc::new("jgt", "jgt @<label>", "Jumps to the label if the last `cmp` or `scmp` found the top value greater than the value it was compared with."),
+    InstructionDoc::new("jlt", "jlt @<label>", "Jumps to the label if the last `cmp` or `scmp` found the top value less than the value it was compared with."),
+    InstructionDoc::new("jge", "jge @<label>", "Jumps to the label if the last `cmp` or `scmp` found the top value greater than or equal to the value it was compared with."),
+    InstructionDoc::new("jle", "jle @<label>", "Jumps to the label if the last `cmp` or `scmp` found the top value less than or equal to the value it was compared with."),
+    InstructionDoc::new("jz", "jz @<label>", "Jumps to the label if the last `cmp` or `scmp` found the values equal."),
+    InstructionDoc::new("jnz", "jnz @<label>", "Jumps to the label if the last `cmp` or `scmp` found the values not equal."),
+    InstructionDoc::new("jneg", "jneg @<label>", "Jumps to the label if the last `cmp` or `scmp` found the top value less than the value it was compared with."),
+    InstructionDoc::new("call", "call @<label> [<args> [<results>]]", "Calls the label with the top `args` values as its arguments. Its `ret` fails unless exactly `results` values are left in their place."),
+    InstructionDoc::new("cmp", "cmp <value>", "Compares the top value from the stack with the value, or the value of a named constant, and sets the flags. The stack is left unchanged."),
+    InstructionDoc::new("scmp", "scmp", "Compares the top two values from the stack and sets the flags. The stack is left unchanged."),
+    InstructionDoc::new("exit", "exit", "Exits the program."),
+    InstructionDoc::new("ret", "ret", "Returns from a subroutine to the instruction after the jump that called it."),
+    InstructionDoc::new("try", "try @<label>", "Starts a block that catches runtime errors: the stack and the calls are unwound to where the block started, and execution continues at the label with the error value pushed."),
+    InstructionDoc::new("endtry", "endtry", "Ends the innermost `try` block."),
+    InstructionDoc::new("throw", "throw", "Pops the top value from the stack and raises it as an error, which the innermost `try` block catches."),
+    InstructionDoc::new("assert", "assert", "Pops the top value from the stack and fails if it is falsy (`0`, `0.0` or `\"\"`)."),
+    InstructionDoc::new("assert_eq", "assert_eq \"<message>\"", "Pops the top two values from the stack and fails with the message if they are not equal."),
+    InstructionDoc::new("rand", "rand", "Pushes a random float between `0` and `1` to the stack."),
+    InstructionDoc::new("srand", "srand", "Pops an integer from the stack and uses it to seed the random number generator."),
+    InstructionDoc::new("randint", "randint <lo> <hi>", "Pushes a random integer between `lo` and `hi` (inclusive) to the stack."),
+    InstructionDoc::new("time", "time", "Pushes the current time to the stack. Not implemented yet."),
+];
+
+/// Returns the documentation for `mnemonic`.
+pub fn instruction(mnemonic: &str) -> Option<&'static InstructionDoc> {
+    INSTRUCTIONS.iter().find(|doc| doc.mnemonic == mnemonic)
+}
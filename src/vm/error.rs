@@ -1,26 +1,457 @@
-use colored::Colorize;
+use colored::{Color, Colorize};
+use serde_json::{json, Value};
+use std::fmt;
 
+/// How many errors are collected before tokenizing or compiling stops.
+pub const DEFAULT_MAX_ERRORS: usize = 20;
+
+/// A range of characters on a single source line. Lines and columns start at 1.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub length: usize,
+    /// Index of the file in the [`SourceMap`], `0` for the main program.
+    pub file: usize,
+    /// Index of the macro expansion in the [`SourceMap`] this code was
+    /// copied into, `0` for code that was written out.
+    pub expansion: usize,
+}
+
+impl Span {
+    pub fn new(line: usize, column: usize, length: usize) -> Span {
+        Span {
+            line,
+            column,
+            length,
+            file: 0,
+            expansion: 0,
+        }
+    }
+
+    /// Whether both spans are on the same line of the same file, and come
+    /// from the same macro expansion.
+    pub fn same_line(&self, other: Span) -> bool {
+        self.file == other.file && self.line == other.line && self.expansion == other.expansion
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+
+    fn color(&self) -> Color {
+        match self {
+            Severity::Error => Color::Red,
+            Severity::Warning => Color::Yellow,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SourceFile {
+    pub name: String,
+    pub source: String,
+}
+
+/// A use of a macro, which copied the macro body into the program.
+#[derive(Clone)]
+pub struct Expansion {
+    pub name: String,
+    pub call_site: Span,
+}
+
+/// The files a program was loaded from, indexed by [`Span::file`], and the
+/// macro expansions in it, indexed by [`Span::expansion`].
+#[derive(Default, Clone)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+    expansions: Vec<Expansion>,
+}
+
+impl SourceMap {
+    /// Creates a source map holding only the main program.
+    pub fn single(name: &str, source: &str) -> SourceMap {
+        let mut sources = SourceMap::default();
+        sources.add(name, source);
+        sources
+    }
+
+    /// Adds a file and returns its index.
+    pub fn add(&mut self, name: &str, source: &str) -> usize {
+        self.files.push(SourceFile {
+            name: name.to_string(),
+            source: source.to_string(),
+        });
+        self.files.len() - 1
+    }
+
+    pub fn get(&self, file: usize) -> Option<&SourceFile> {
+        self.files.get(file)
+    }
+
+    pub fn files(&self) -> &[SourceFile] {
+        &self.files
+    }
+
+    pub fn expansions(&self) -> &[Expansion] {
+        &self.expansions
+    }
+
+    /// Adds a macro expansion and returns its index, which starts at `1`.
+    pub fn add_expansion(&mut self, name: &str, call_site: Span) -> usize {
+        self.expansions.push(Expansion {
+            name: name.to_string(),
+            call_site,
+        });
+        self.expansions.len()
+    }
+
+    pub fn expansion(&self, expansion: usize) -> Option<&Expansion> {
+        self.expansions.get(expansion.checked_sub(1)?)
+    }
+
+    fn name(&self, span: Span) -> Option<String> {
+        self.get(span.file).map(|file| file.name.clone())
+    }
+
+    /// Formats `span` as `file:line:col`.
+    pub fn position(&self, span: Span) -> String {
+        match self.get(span.file) {
+            Some(file) => format!("{}:{}", file.name, span),
+            None => span.to_string(),
+        }
+    }
+
+    /// Adds a note for every macro use `error` is inside of, and names the
+    /// notes after the files their spans point into.
+    pub fn annotate(&self, error: Error) -> Error {
+        let mut error = error;
+
+        let mut span = error.span;
+        while let Some(expansion) = span.and_then(|span| self.expansion(span.expansion)) {
+            let message = format!("in this expansion of macro `{}`", expansion.name);
+            error = error.with_note(&message, expansion.call_site);
+            span = Some(expansion.call_site);
+        }
+
+        for note in &mut error.notes {
+            if note.file.is_none() {
+                note.file = self.name(note.span);
+            }
+        }
+
+        error
+    }
+
+    /// Names `error` after the file its span points into, unless it already
+    /// names one, annotates it and returns the source of its file.
+    fn locate(&self, error: Error) -> (Error, &str) {
+        let error = self.annotate(error);
+        let file = self.get(error.span.map_or(0, |span| span.file));
+        let source = file.map_or("", |file| file.source.as_str());
+        match (file, &error.file) {
+            (Some(file), None) => (error.in_file(&file.name), source),
+            _ => (error, source),
+        }
+    }
+
+    /// Renders `error` with the source line it points to, followed by its
+    /// notes.
+    pub fn render(&self, error: Error) -> String {
+        let (error, source) = self.locate(error);
+        self.render_located(&error, source)
+    }
+
+    fn render_located(&self, error: &Error, source: &str) -> String {
+        let mut out = error.render(source);
+        for note in &error.notes {
+            let source = self
+                .get(note.span.file)
+                .map_or("", |file| file.source.as_str());
+            out += &note.render(source);
+        }
+        out
+    }
+}
+
+/// How diagnostics are printed by the command line tools.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorFormat {
+    /// Colored, source-annotated text.
+    Human,
+    /// One JSON object per line.
+    Json,
+}
+
+/// A secondary position shown with an error, such as the definition of a
+/// macro that was used wrongly.
+#[derive(Debug, Clone)]
+pub struct Note {
+    pub message: String,
+    pub file: Option<String>,
+    pub span: Span,
+}
+
+impl Note {
+    fn render(&self, source: &str) -> String {
+        let mut out = format!("{}: {}\n", "note".bold(), self.message);
+        let location = match &self.file {
+            Some(file) => format!("{}:{}", file, self.span),
+            None => self.span.to_string(),
+        };
+        out += &snippet(&location, self.span, source, Color::Blue);
+        out
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "message": self.message,
+            "file": self.file,
+            "line": self.span.line,
+            "column": self.span.column,
+        })
+    }
+}
+
+/// Renders the `-->` location line and the source line `span` points to,
+/// underlined in `color`. Returns only the location line if the source line
+/// does not exist.
+fn snippet(location: &str, span: Span, source: &str, color: Color) -> String {
+    let Some(text) = source.lines().nth(span.line.saturating_sub(1)) else {
+        return format!(" {} {}\n", "-->".blue().bold(), location);
+    };
+
+    let gutter = " ".repeat(span.line.to_string().len());
+    let underline = format!(
+        "{}{}",
+        " ".repeat(span.column.saturating_sub(1)),
+        "^".repeat(span.length.max(1))
+    );
+
+    let mut out = format!("{}{} {}\n", gutter, "-->".blue().bold(), location);
+    out += &format!("{} {}\n", gutter, "|".blue().bold());
+    out += &format!(
+        "{} {} {}\n",
+        span.line.to_string().blue().bold(),
+        "|".blue().bold(),
+        text
+    );
+    out += &format!(
+        "{} {} {}\n",
+        gutter,
+        "|".blue().bold(),
+        underline.color(color).bold()
+    );
+    out
+}
+
+/// An error or warning reported by the tokenizer, the compiler or the VM.
+///
+/// Error codes are grouped by stage: `E00xx` for the tokenizer, `E01xx` for
+/// the compiler and `E02xx` for runtime errors. Warnings from the stack-effect
+/// verifier use `W01xx` and warnings from the linter `W03xx`.
+#[derive(Debug, Clone)]
 pub struct Error {
+    pub severity: Severity,
+    pub code: &'static str,
     pub message: String,
-    pub pos: String,
+    pub file: Option<String>,
+    pub span: Option<Span>,
+    pub help: Vec<String>,
+    pub notes: Vec<Note>,
 }
 
 impl Error {
-    pub fn new(message: &str, pos: String) -> Error {
+    pub fn new(code: &'static str, message: &str, span: Span) -> Error {
         Error {
+            severity: Severity::Error,
+            code,
             message: message.to_string(),
-            pos: pos.to_string(),
+            file: None,
+            span: Some(span),
+            help: Vec::new(),
+            notes: Vec::new(),
         }
     }
 
-    pub fn print(&self) {
-        let error_message = format!(
-            "{}{}{}{}",
-            "[ERROR at position ".red(),
-            self.pos.red(),
-            "] > ".red(),
-            self.message.red()
+    /// Creates an error that is not tied to a position in the source.
+    pub fn unpositioned(code: &'static str, message: &str) -> Error {
+        Error {
+            severity: Severity::Error,
+            code,
+            message: message.to_string(),
+            file: None,
+            span: None,
+            help: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn warning(code: &'static str, message: &str, span: Span) -> Error {
+        Error {
+            severity: Severity::Warning,
+            ..Error::new(code, message, span)
+        }
+    }
+
+    pub fn with_help(mut self, help: &str) -> Error {
+        self.help.push(help.to_string());
+        self
+    }
+
+    pub fn with_note(mut self, message: &str, span: Span) -> Error {
+        self.notes.push(Note {
+            message: message.to_string(),
+            file: None,
+            span,
+        });
+        self
+    }
+
+    pub fn in_file(mut self, file: &str) -> Error {
+        self.file = Some(file.to_string());
+        self
+    }
+
+    /// Renders the error in the style of rustc, quoting the offending line
+    /// of `source` and underlining the span.
+    pub fn render(&self, source: &str) -> String {
+        let color = self.severity.color();
+        let mut out = format!(
+            "{}{}\n",
+            format!("{}[{}]", self.severity.as_str(), self.code)
+                .color(color)
+                .bold(),
+            format!(": {}", self.message).bold()
         );
-        eprintln!("{}", error_message);
+
+        let location = match (&self.file, &self.span) {
+            (Some(file), Some(span)) => Some(format!("{}:{}", file, span)),
+            (Some(file), None) => Some(file.clone()),
+            (None, Some(span)) => Some(span.to_string()),
+            (None, None) => None,
+        };
+
+        let line = self
+            .span
+            .filter(|span| source.lines().nth(span.line.saturating_sub(1)).is_some());
+
+        let gutter = match line {
+            Some(span) => " ".repeat(span.line.to_string().len()),
+            None => " ".to_string(),
+        };
+
+        match (location, line) {
+            (Some(location), Some(span)) => out += &snippet(&location, span, source, color),
+            (Some(location), None) => {
+                out += &format!("{}{} {}\n", gutter, "-->".blue().bold(), location);
+            }
+            _ => {}
+        }
+
+        if !self.help.is_empty() {
+            out += &format!("{} {}\n", gutter, "|".blue().bold());
+        }
+
+        for help in &self.help {
+            out += &format!("{} {} help: {}\n", gutter, "=".blue().bold(), help);
+        }
+
+        out
+    }
+
+    /// Converts the error to the object printed by `--error-format=json`.
+    /// End columns are exclusive.
+    pub fn to_json(&self) -> Value {
+        json!({
+            "file": self.file,
+            "start_line": self.span.map(|span| span.line),
+            "start_column": self.span.map(|span| span.column),
+            "end_line": self.span.map(|span| span.line),
+            "end_column": self.span.map(|span| span.column + span.length),
+            "severity": self.severity.as_str(),
+            "code": self.code,
+            "message": self.message,
+            "help": self.help,
+            "notes": self.notes.iter().map(Note::to_json).collect::<Vec<_>>(),
+        })
+    }
+
+    pub fn print(&self, source: &str) {
+        eprint!("{}", self.render(source));
+    }
+}
+
+/// Prints `errors` to stderr, sorted by position. Each error is shown with
+/// the file its span points into.
+pub fn print_all(errors: Vec<Error>, sources: &SourceMap, format: ErrorFormat) {
+    let mut errors = errors;
+    errors.sort_by_key(|error| error.span.map(|span| (span.file, span.line, span.column)));
+
+    let count = errors
+        .iter()
+        .filter(|error| error.severity == Severity::Error)
+        .count();
+
+    for error in errors {
+        let (error, source) = sources.locate(error);
+        match format {
+            ErrorFormat::Human => eprintln!("{}", sources.render_located(&error, source)),
+            ErrorFormat::Json => eprintln!("{}", error.to_json()),
+        }
     }
+
+    if format == ErrorFormat::Human && count > 1 {
+        eprintln!("error: aborting due to {} previous errors", count);
+    }
+}
+
+/// Returns the candidate closest to `name`, if it is close enough to be a
+/// likely typo.
+pub fn suggest<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, candidate)| *distance <= (candidate.len().max(name.len()) / 3).max(1))
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+
+        for (j, cb) in b.iter().enumerate() {
+            let current = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                previous
+            } else {
+                1 + previous.min(row[j]).min(row[j + 1])
+            };
+            previous = current;
+        }
+    }
+
+    row[b.len()]
 }
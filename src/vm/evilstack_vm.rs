@@ -1,65 +1,409 @@
 use crate::{
-    error::Error,
+    constants,
+    coverage::Coverage,
+    error::{suggest, Error, Severity, SourceMap, Span, DEFAULT_MAX_ERRORS},
+    history::{Change, History, MAX_POPPED},
+    limits::{Limits, CHECK_INTERVAL},
+    lint,
+    profile::Profiler,
+    snapshot::{Snapshot, State},
     tokenizer::{Symbol, SymbolType},
+    trace::Tracer,
+    typecheck, verifier,
 };
-use rand::Rng;
-use std::{collections::HashMap, io};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{collections::HashMap, fmt, io, ops::Deref, time::Instant};
 
 #[derive(Debug, Clone)]
-enum ConstType {
+pub(crate) enum ConstType {
     Integer(i32),
     Float(f32),
     String(String),
 }
 
+impl ConstType {
+    fn is_truthy(&self) -> bool {
+        match self {
+            ConstType::Integer(i) => *i != 0,
+            ConstType::Float(f) => *f != 0.0,
+            ConstType::String(s) => !s.is_empty(),
+        }
+    }
+}
+
+impl ConstType {
+    /// Bytes of memory the value counts for against the heap limit.
+    fn size(&self) -> usize {
+        match self {
+            ConstType::Integer(_) | ConstType::Float(_) => 4,
+            ConstType::String(s) => s.len(),
+        }
+    }
+}
+
+impl PartialEq for ConstType {
+    fn eq(&self, other: &ConstType) -> bool {
+        match (self, other) {
+            (ConstType::Integer(a), ConstType::Integer(b)) => a == b,
+            (ConstType::Float(a), ConstType::Float(b)) => a == b,
+            (ConstType::Float(a), ConstType::Integer(b)) => *a == *b as f32,
+            (ConstType::Integer(a), ConstType::Float(b)) => *a as f32 == *b,
+            (ConstType::String(a), ConstType::String(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for ConstType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConstType::Integer(i) => write!(f, "{}", i),
+            ConstType::Float(x) => write!(f, "{}", x),
+            ConstType::String(s) => write!(f, "{:?}", s),
+        }
+    }
+}
+
+/// The value stack, which keeps count of the bytes its values hold so the
+/// heap limit is checked without walking it.
+#[derive(Default)]
+struct Stack {
+    values: Vec<ConstType>,
+    bytes: usize,
+}
+
+impl Stack {
+    fn push(&mut self, value: ConstType) {
+        self.bytes += value.size();
+        self.values.push(value);
+    }
+
+    fn pop(&mut self) -> Option<ConstType> {
+        let value = self.values.pop()?;
+        self.bytes -= value.size();
+        Some(value)
+    }
+
+    fn truncate(&mut self, len: usize) {
+        while self.values.len() > len {
+            self.pop();
+        }
+    }
+
+    fn extend(&mut self, values: impl IntoIterator<Item = ConstType>) {
+        for value in values {
+            self.push(value);
+        }
+    }
+}
+
+impl From<Vec<ConstType>> for Stack {
+    fn from(values: Vec<ConstType>) -> Stack {
+        let bytes = values.iter().map(ConstType::size).sum();
+        Stack { values, bytes }
+    }
+}
+
+impl Deref for Stack {
+    type Target = [ConstType];
+
+    fn deref(&self) -> &[ConstType] {
+        &self.values
+    }
+}
+
+/// Bytes held by the local variables of `frame`.
+fn frame_size(frame: &Frame) -> usize {
+    frame.locals.values().map(ConstType::size).sum()
+}
+
+/// Every instruction mnemonic understood by the compiler.
+pub const MNEMONICS: &[&str] = &[
+    "push",
+    "pop",
+    "dup",
+    "swap",
+    "set",
+    "get",
+    "add",
+    "sub",
+    "mul",
+    "div",
+    "idiv",
+    "mod",
+    "print",
+    "read",
+    "atoi",
+    "ftoi",
+    "jmp",
+    "jeq",
+    "jne",
+    "jgt",
+    "jlt",
+    "jge",
+    "jle",
+    "jz",
+    "jnz",
+    "jneg",
+    "call",
+    "cmp",
+    "scmp",
+    "exit",
+    "ret",
+    "try",
+    "endtry",
+    "throw",
+    "assert",
+    "assert_eq",
+    "rand",
+    "srand",
+    "randint",
+    "time",
+];
+
+/// Runtime errors that a `try` block catches. The others are mistakes in the
+/// program rather than in the data it works on.
+const CATCHABLE: &[&str] = &[
+    "E0201", "E0202", "E0203", "E0205", "E0206", "E0209", "E0211", "E0212", "E0218",
+];
+
+/// How many calls a stack trace shows, innermost first.
+const MAX_TRACE: usize = 16;
+
+/// How many global registers there are, named `r0` to `r7`.
+pub const REGISTERS: usize = 8;
+
+/// A named storage slot read by `get` and written by `set`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Variable {
+    /// A local variable of the current call frame.
+    Local(String),
+    /// One of the global registers.
+    Register(usize),
+}
+
+impl Variable {
+    fn new(name: &str) -> Variable {
+        match name.strip_prefix('r').and_then(|index| index.parse().ok()) {
+            Some(index) if index < REGISTERS && name.len() == 2 => Variable::Register(index),
+            _ => Variable::Local(name.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Variable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Variable::Local(name) => write!(f, "variable `{}`", name),
+            Variable::Register(index) => write!(f, "register `r{}`", index),
+        }
+    }
+}
+
 #[derive(Debug)]
-enum Instruction {
+pub(crate) enum Instruction {
     // Stack operations
-    Push(ConstType, String),
-    Pop(String),
-    Duplicate(String),
-    Swap(String),
+    Push(ConstType, Span),
+    Pop(Span),
+    Duplicate(Span),
+    Swap(Span),
+
+    // Variables
+    Set(Variable, Span),
+    Get(Variable, Span),
+
+    // Calls with a declared number of arguments and results
+    Call(String, usize, usize, Span),
 
     // Binary operations
-    Add(String),
-    Sub(String),
-    Mul(String),
-    Div(String),
-    IDiv(String),
-    Mod(String),
+    Add(Span),
+    Sub(Span),
+    Mul(Span),
+    Div(Span),
+    IDiv(Span),
+    Mod(Span),
 
     // Control flow
-    Label(String, String),
-    CmpInStack(String),
-    Cmp(ConstType, String),
-    Return(String),
-    Jump(String, String),
-    JumpEq(String, String),
-    JumpNotEq(String, String),
-    JumpGt(String, String),
-    JumpLt(String, String),
-    JumpGtEq(String, String),
-    JumpLtEq(String, String),
-    JumpZero(String, String),
-    JumpNotZero(String, String),
-    JumpNeg(String, String),
-    Exit(String),
+    Label(String, Span),
+    CmpInStack(Span),
+    Cmp(ConstType, Span),
+    Return(Span),
+    Jump(String, Span),
+    JumpEq(String, Span),
+    JumpNotEq(String, Span),
+    JumpGt(String, Span),
+    JumpLt(String, Span),
+    JumpGtEq(String, Span),
+    JumpLtEq(String, Span),
+    JumpZero(String, Span),
+    JumpNotZero(String, Span),
+    JumpNeg(String, Span),
+    Exit(Span),
+
+    // Exceptions
+    Try(String, Span),
+    EndTry(Span),
+    Throw(Span),
+
+    // Testing
+    Assert(Span),
+    AssertEq(String, Span),
 
     // I/O
-    Print(String),
-    Read(String),
+    Print(Span),
+    Read(Span),
 
     // Type conversion
-    AToI(String),
-    IToA(String),
-    IToF(String),
-    FToI(String),
+    AToI(Span),
+    // No mnemonic compiles to these yet, see TODO.md.
+    #[allow(dead_code)]
+    IToA(Span),
+    #[allow(dead_code)]
+    IToF(Span),
+    FToI(Span),
 
     // Built-in functions
-    Random(String),
-    Time(String),
+    Random(Span),
+    SeedRandom(Span),
+    RandomInt(i32, i32, Span),
+    Time(Span),
+}
+
+impl Instruction {
+    pub(crate) fn pos(&self) -> Span {
+        match self {
+            Instruction::Pop(pos)
+            | Instruction::Duplicate(pos)
+            | Instruction::Swap(pos)
+            | Instruction::Add(pos)
+            | Instruction::Sub(pos)
+            | Instruction::Mul(pos)
+            | Instruction::Div(pos)
+            | Instruction::IDiv(pos)
+            | Instruction::Mod(pos)
+            | Instruction::CmpInStack(pos)
+            | Instruction::Return(pos)
+            | Instruction::Exit(pos)
+            | Instruction::EndTry(pos)
+            | Instruction::Throw(pos)
+            | Instruction::Assert(pos)
+            | Instruction::Print(pos)
+            | Instruction::Read(pos)
+            | Instruction::AToI(pos)
+            | Instruction::IToA(pos)
+            | Instruction::IToF(pos)
+            | Instruction::FToI(pos)
+            | Instruction::Random(pos)
+            | Instruction::SeedRandom(pos)
+            | Instruction::Time(pos) => *pos,
+            Instruction::Push(_, pos)
+            | Instruction::Set(_, pos)
+            | Instruction::Get(_, pos)
+            | Instruction::Label(_, pos)
+            | Instruction::Cmp(_, pos)
+            | Instruction::Jump(_, pos)
+            | Instruction::JumpEq(_, pos)
+            | Instruction::JumpNotEq(_, pos)
+            | Instruction::JumpGt(_, pos)
+            | Instruction::JumpLt(_, pos)
+            | Instruction::JumpGtEq(_, pos)
+            | Instruction::JumpLtEq(_, pos)
+            | Instruction::JumpZero(_, pos)
+            | Instruction::JumpNotZero(_, pos)
+            | Instruction::JumpNeg(_, pos)
+            | Instruction::Try(_, pos)
+            | Instruction::AssertEq(_, pos) => *pos,
+            Instruction::RandomInt(_, _, pos) | Instruction::Call(_, _, _, pos) => *pos,
+        }
+    }
+
+    /// Returns the label a jump instruction refers to.
+    pub(crate) fn target(&self) -> Option<&str> {
+        match self {
+            Instruction::Jump(label, _)
+            | Instruction::JumpEq(label, _)
+            | Instruction::JumpNotEq(label, _)
+            | Instruction::JumpGt(label, _)
+            | Instruction::JumpLt(label, _)
+            | Instruction::JumpGtEq(label, _)
+            | Instruction::JumpLtEq(label, _)
+            | Instruction::JumpZero(label, _)
+            | Instruction::JumpNotZero(label, _)
+            | Instruction::JumpNeg(label, _)
+            | Instruction::Call(label, _, _, _)
+            | Instruction::Try(label, _) => Some(label),
+            _ => None,
+        }
+    }
+}
+
+/// Shows the instruction the way it is written in the source.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let variable = |variable: &Variable| match variable {
+            Variable::Local(name) => name.clone(),
+            Variable::Register(index) => format!("r{}", index),
+        };
+
+        match self {
+            Instruction::Push(value, _) => write!(f, "push {}", value),
+            Instruction::Pop(_) => write!(f, "pop"),
+            Instruction::Duplicate(_) => write!(f, "dup"),
+            Instruction::Swap(_) => write!(f, "swap"),
+            Instruction::Set(name, _) => write!(f, "set {}", variable(name)),
+            Instruction::Get(name, _) => write!(f, "get {}", variable(name)),
+            Instruction::Call(label, args, results, _) => {
+                write!(f, "call @{} {} {}", label, args, results)
+            }
+            Instruction::Add(_) => write!(f, "add"),
+            Instruction::Sub(_) => write!(f, "sub"),
+            Instruction::Mul(_) => write!(f, "mul"),
+            Instruction::Div(_) => write!(f, "div"),
+            Instruction::IDiv(_) => write!(f, "idiv"),
+            Instruction::Mod(_) => write!(f, "mod"),
+            Instruction::Label(label, _) => write!(f, "{}:", label),
+            Instruction::CmpInStack(_) => write!(f, "scmp"),
+            Instruction::Cmp(value, _) => write!(f, "cmp {}", value),
+            Instruction::Return(_) => write!(f, "ret"),
+            Instruction::Jump(label, _) => write!(f, "jmp @{}", label),
+            Instruction::JumpEq(label, _) => write!(f, "jeq @{}", label),
+            Instruction::JumpNotEq(label, _) => write!(f, "jne @{}", label),
+            Instruction::JumpGt(label, _) => write!(f, "jgt @{}", label),
+            Instruction::JumpLt(label, _) => write!(f, "jlt @{}", label),
+            Instruction::JumpGtEq(label, _) => write!(f, "jge @{}", label),
+            Instruction::JumpLtEq(label, _) => write!(f, "jle @{}", label),
+            Instruction::JumpZero(label, _) => write!(f, "jz @{}", label),
+            Instruction::JumpNotZero(label, _) => write!(f, "jnz @{}", label),
+            Instruction::JumpNeg(label, _) => write!(f, "jneg @{}", label),
+            Instruction::Exit(_) => write!(f, "exit"),
+            Instruction::Try(label, _) => write!(f, "try @{}", label),
+            Instruction::EndTry(_) => write!(f, "endtry"),
+            Instruction::Throw(_) => write!(f, "throw"),
+            Instruction::Assert(_) => write!(f, "assert"),
+            Instruction::AssertEq(message, _) => write!(f, "assert_eq {:?}", message),
+            Instruction::Print(_) => write!(f, "print"),
+            Instruction::Read(_) => write!(f, "read"),
+            Instruction::AToI(_) => write!(f, "atoi"),
+            Instruction::IToA(_) => write!(f, "itoa"),
+            Instruction::IToF(_) => write!(f, "itof"),
+            Instruction::FToI(_) => write!(f, "ftoi"),
+            Instruction::Random(_) => write!(f, "rand"),
+            Instruction::SeedRandom(_) => write!(f, "srand"),
+            Instruction::RandomInt(lo, hi, _) => write!(f, "randint {} {}", lo, hi),
+            Instruction::Time(_) => write!(f, "time"),
+        }
+    }
 }
 
+/// The name of the label at `ip`.
+fn label_at(program: &[Instruction], ip: usize) -> &str {
+    match program[ip] {
+        Instruction::Label(ref label, _) => label,
+        _ => "",
+    }
+}
+
+#[derive(Clone)]
 pub struct Flags {
     pub zero: bool,
     pub negative: bool,
@@ -71,20 +415,98 @@ pub struct Flags {
     pub less_than_or_equal: bool,
 }
 
+/// An active subroutine call.
+#[derive(Clone)]
+pub(crate) struct Frame {
+    /// Where execution continues after `ret`.
+    pub return_address: usize,
+    /// Position of the callee's label in the program.
+    pub callee: usize,
+    /// Position of the jump or `call` that made the call.
+    pub call_site: Span,
+    /// Arguments and results declared by `call`, `None` for jumps.
+    pub signature: Option<(usize, usize)>,
+    /// Height of the stack below the arguments.
+    pub base: usize,
+    /// Local variables of a `call`. Jumps share those of their caller.
+    pub locals: HashMap<String, ConstType>,
+}
+
+/// Whether `instruction` may grow the heap, which is then measured against
+/// its limit.
+fn allocates(instruction: &Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::Push(_, _)
+            | Instruction::Duplicate(_)
+            | Instruction::Get(_, _)
+            | Instruction::Set(_, _)
+            | Instruction::Add(_)
+            | Instruction::Read(_)
+            | Instruction::IToA(_)
+    )
+}
+
+/// A `try` block that has not ended yet.
+#[derive(Clone)]
+pub(crate) struct Handler {
+    /// Position of the handler's label in the program.
+    pub target: usize,
+    /// Height of the stack when the block started.
+    pub depth: usize,
+    /// Number of active calls when the block started.
+    pub calls: usize,
+}
+
 pub struct EvilStackVM {
-    stack: Vec<ConstType>,
+    stack: Stack,
     ip: usize,
     symbols: Vec<Symbol>,
     program: Vec<Instruction>,
     labels: HashMap<String, usize>,
     flags: Flags,
-    return_stack: Vec<usize>,
+    /// Active calls, innermost last.
+    call_stack: Vec<Frame>,
+    /// Local variables of the top level.
+    locals: HashMap<String, ConstType>,
+    registers: [Option<ConstType>; REGISTERS],
+    /// Bytes held by the values in variables and in registers.
+    variables: usize,
+    /// Active `try` blocks, innermost last.
+    handlers: Vec<Handler>,
+    /// The value of the last `throw`, until it is caught.
+    thrown: Option<ConstType>,
+    rng: StdRng,
+    max_errors: usize,
+    /// How many values from the top of the stack runtime errors show.
+    stack_dump: usize,
+    /// With a stack dump, the height of the stack the current instruction
+    /// does not reach below and the values above it before it ran.
+    before: Option<(usize, Vec<ConstType>)>,
+    limits: Limits,
+    /// When the run has to stop, from the time limit.
+    deadline: Option<Instant>,
+    /// Instructions executed so far.
+    steps: u64,
+    /// Whether the program is denied access to stdin.
+    sandbox: bool,
+    tracer: Option<Tracer>,
+    profiler: Option<Profiler>,
+    coverage: Option<Coverage>,
+    /// The undo log, if steps can be undone.
+    history: Option<History>,
+    /// Lines read by undone steps, which `read` returns again, last first.
+    input: Vec<String>,
+    /// The outcome of the first [`EvilStackVM::load`], which later calls
+    /// return again.
+    loaded: Option<Result<(), Vec<Error>>>,
+    warnings: Vec<Error>,
 }
 
 impl EvilStackVM {
     pub fn new(symbols: Vec<Symbol>) -> EvilStackVM {
         EvilStackVM {
-            stack: Vec::new(),
+            stack: Stack::default(),
             ip: 0,
             symbols,
             program: Vec::new(),
@@ -99,675 +521,1716 @@ impl EvilStackVM {
                 greater_than_or_equal: false,
                 less_than_or_equal: false,
             },
-            return_stack: Vec::new(),
+            call_stack: Vec::new(),
+            locals: HashMap::new(),
+            registers: Default::default(),
+            variables: 0,
+            handlers: Vec::new(),
+            thrown: None,
+            rng: StdRng::from_entropy(),
+            max_errors: DEFAULT_MAX_ERRORS,
+            stack_dump: 0,
+            before: None,
+            limits: Limits::default(),
+            deadline: None,
+            steps: 0,
+            sandbox: false,
+            tracer: None,
+            profiler: None,
+            coverage: None,
+            history: None,
+            input: Vec::new(),
+            loaded: None,
+            warnings: Vec::new(),
+        }
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> EvilStackVM {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    /// Sets how many compile errors are collected before compilation stops.
+    pub fn with_max_errors(mut self, max_errors: usize) -> EvilStackVM {
+        self.max_errors = max_errors;
+        self
+    }
+
+    /// Sets how many values from the top of the stack are shown with a
+    /// runtime error.
+    pub fn with_stack_dump(mut self, values: usize) -> EvilStackVM {
+        self.stack_dump = values;
+        self
+    }
+
+    /// Sets the resource limits the run is stopped at.
+    pub fn with_limits(mut self, limits: Limits) -> EvilStackVM {
+        self.limits = limits;
+        self
+    }
+
+    /// Denies the program access to stdin, so `read` fails.
+    pub fn with_sandbox(mut self, sandbox: bool) -> EvilStackVM {
+        self.sandbox = sandbox;
+        self
+    }
+
+    /// Writes a trace of every executed instruction to `tracer`.
+    pub fn with_tracer(mut self, tracer: Tracer) -> EvilStackVM {
+        self.tracer = Some(tracer);
+        self
+    }
+
+    /// Counts and times every executed instruction with `profiler`.
+    pub fn with_profiler(mut self, profiler: Profiler) -> EvilStackVM {
+        self.profiler = Some(profiler);
+        self
+    }
+
+    /// The profile of the run, if a profiler was set.
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    /// Records which instructions and conditional-jump outcomes ran into
+    /// `coverage`.
+    pub fn with_coverage(mut self, coverage: Coverage) -> EvilStackVM {
+        self.coverage = Some(coverage);
+        self
+    }
+
+    /// The coverage of the run, if it was recorded.
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    /// Keeps an undo log of the latest `capacity` steps, so they can be
+    /// undone by [`EvilStackVM::step_back`].
+    pub fn with_history(mut self, capacity: usize) -> EvilStackVM {
+        self.history = Some(History::new(capacity));
+        self
+    }
+
+    pub fn execute(&mut self) -> Result<(), Vec<Error>> {
+        self.load()?;
+        self.run().map_err(|error| vec![self.trace(error)])
+    }
+
+    /// Runs the subroutine at `label` as if it had been called from the top
+    /// level: a `ret` with no caller left ends the run successfully.
+    pub fn execute_label(&mut self, label: &str) -> Result<(), Vec<Error>> {
+        self.load()?;
+
+        match self.labels.get(label) {
+            Some(ip) => {
+                self.ip = *ip;
+                self.call_stack.push(Frame {
+                    return_address: self.program.len(),
+                    callee: *ip,
+                    call_site: self.program[*ip].pos(),
+                    signature: None,
+                    base: 0,
+                    locals: HashMap::new(),
+                });
+            }
+            None => {
+                return Err(vec![Error::unpositioned(
+                    "E0200",
+                    &format!("Unknown label: {}", label),
+                )]);
+            }
+        }
+
+        self.run().map_err(|error| vec![self.trace(error)])
+    }
+
+    /// Runs the next instruction, catching errors like
+    /// [`EvilStackVM::execute`]. Returns whether the program goes on.
+    pub fn step_forward(&mut self) -> Result<bool, Vec<Error>> {
+        self.load()?;
+        if self.ip >= self.program.len() {
+            return Ok(false);
+        }
+
+        match self.step() {
+            Ok(running) => Ok(running && self.ip < self.program.len()),
+            Err(error) if CATCHABLE.contains(&error.code) && !self.handlers.is_empty() => {
+                self.catch(error);
+                Ok(true)
+            }
+            Err(error) => Err(vec![self.trace(error)]),
+        }
+    }
+
+    /// Undoes the latest step still in the undo log set by
+    /// [`EvilStackVM::with_history`]. Returns `false` if there is none.
+    pub fn step_back(&mut self) -> bool {
+        match self.history.as_mut().and_then(History::pop) {
+            Some(change) => {
+                self.undo(change);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The instruction that runs next, `None` past the end of the program.
+    pub(crate) fn next_instruction(&self) -> Option<&Instruction> {
+        self.program.get(self.ip)
+    }
+
+    pub(crate) fn program(&self) -> &[Instruction] {
+        &self.program
+    }
+
+    pub(crate) fn stack(&self) -> &[ConstType] {
+        &self.stack
+    }
+
+    /// Captures the program, loaded from `sources`, and the state of the run
+    /// so it can be continued later by [`EvilStackVM::restore`]. The random
+    /// number generator is reseeded from itself, so this run and the resumed
+    /// one go on drawing the same numbers.
+    pub fn snapshot(&mut self, sources: &SourceMap) -> Snapshot {
+        let seed = self.rng.gen();
+        self.rng = StdRng::seed_from_u64(seed);
+
+        Snapshot {
+            sources: sources.clone(),
+            symbols: self.symbols.clone(),
+            state: State {
+                ip: self.ip,
+                stack: self.stack.to_vec(),
+                flags: self.flags.clone(),
+                calls: self.call_stack.clone(),
+                locals: self.locals.clone(),
+                registers: self.registers.to_vec(),
+                handlers: self.handlers.clone(),
+                seed,
+            },
+        }
+    }
+
+    /// Loads the program and puts the VM in the state captured by
+    /// `snapshot`, so the next [`EvilStackVM::execute`] continues that run.
+    /// The VM has to be created from [`Snapshot::symbols`].
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), Vec<Error>> {
+        self.load()?;
+
+        let state = &snapshot.state;
+        if let Some(problem) = self.mismatch(state) {
+            let message = format!("Snapshot does not match its program: {}", problem);
+            return Err(vec![Error::unpositioned("E0217", &message)]);
+        }
+
+        self.ip = state.ip;
+        self.stack = Stack::from(state.stack.clone());
+        self.flags = state.flags.clone();
+        self.call_stack = state.calls.clone();
+        self.locals = state.locals.clone();
+        for (register, value) in self.registers.iter_mut().zip(&state.registers) {
+            *register = value.clone();
+        }
+        self.variables = self.variable_size();
+        self.handlers = state.handlers.clone();
+        self.thrown = None;
+        self.steps = 0;
+        self.rng = StdRng::seed_from_u64(state.seed);
+        Ok(())
+    }
+
+    /// Describes the first position in `state` that is outside the loaded
+    /// program or stack.
+    fn mismatch(&self, state: &State) -> Option<String> {
+        let is_label = |ip: usize| matches!(self.program.get(ip), Some(Instruction::Label(_, _)));
+        let len = self.program.len();
+
+        if state.ip > len {
+            return Some(format!("instruction {} is out of range", state.ip));
+        }
+        if state.registers.len() != REGISTERS {
+            return Some(format!("expected {} registers", REGISTERS));
+        }
+        for frame in &state.calls {
+            if !is_label(frame.callee) {
+                return Some(format!("call to instruction {}", frame.callee));
+            }
+            // `ret` continues after the call, one past the return address.
+            if !(1..=len).contains(&frame.return_address) {
+                let address = frame.return_address;
+                return Some(format!("return address {} is out of range", address));
+            }
+            if frame.base > state.stack.len() {
+                return Some("a call starts above the stack".to_string());
+            }
+        }
+        for handler in &state.handlers {
+            if !is_label(handler.target) {
+                return Some(format!("handler at instruction {}", handler.target));
+            }
+            if handler.depth > state.stack.len() || handler.calls > state.calls.len() {
+                return Some("a try block starts above the stack".to_string());
+            }
+        }
+        None
+    }
+
+    /// Returns every label with the position it is defined at, in program order.
+    pub fn labels(&mut self) -> Result<Vec<(String, Span)>, Vec<Error>> {
+        self.load()?;
+
+        let mut labels = Vec::new();
+        for instruction in &self.program {
+            if let Instruction::Label(ref label, ref pos) = instruction {
+                labels.push((label.clone(), *pos));
+            }
+        }
+
+        Ok(labels)
+    }
+
+    /// Returns the warnings found by the last call to [`EvilStackVM::load`].
+    pub fn warnings(&self) -> &[Error] {
+        &self.warnings
+    }
+
+    /// Infers the type of every stack slot and returns the instructions that
+    /// would fail with a type mismatch on some path. Call after
+    /// [`EvilStackVM::load`].
+    pub fn check_types(&self) -> Vec<Error> {
+        typecheck::check(&self.program, &self.labels)
+    }
+
+    /// Returns warnings for common mistakes such as unreachable code and
+    /// unused labels. Call after [`EvilStackVM::load`].
+    pub fn lint(&self) -> Vec<Error> {
+        lint::lint(&self.program, &self.labels)
+    }
+
+    /// Compiles the symbols and resolves the labels, reporting every error
+    /// found (up to the maximum error count) instead of stopping at the first.
+    /// Only the first call compiles; later ones return its outcome, so a
+    /// program that failed to compile is never run.
+    pub fn load(&mut self) -> Result<(), Vec<Error>> {
+        if let Some(loaded) = &self.loaded {
+            return loaded.clone();
         }
+        let loaded = self.compile_program();
+        self.loaded = Some(loaded.clone());
+        loaded
     }
 
-    pub fn execute(&mut self) {
-        self.compile();
+    fn compile_program(&mut self) -> Result<(), Vec<Error>> {
+        let mut errors = match self.compile() {
+            Ok(()) => Vec::new(),
+            Err(errors) => errors,
+        };
         self.analyze_labels();
+        errors.extend(self.undefined_labels());
+
+        if errors.is_empty() {
+            let (verifier_errors, warnings): (Vec<Error>, Vec<Error>) =
+                verifier::verify(&self.program, &self.labels)
+                    .into_iter()
+                    .partition(|error| error.severity == Severity::Error);
+            errors.extend(verifier_errors);
+            self.warnings = warnings;
+        }
+
+        if !errors.is_empty() {
+            errors.sort_by_key(|error| error.span.map(|span| (span.line, span.column)));
+            errors.truncate(self.max_errors);
+            return Err(errors);
+        }
+
+        Ok(())
+    }
+
+    /// Jumps to `target` as a call, returning to the next instruction.
+    /// `signature` holds the arguments and results declared by `call`, whose
+    /// arguments must be on the stack, and only such a call gets its own
+    /// local variables.
+    fn call(&mut self, target: usize, signature: Option<(usize, usize)>) {
+        let args = signature.map_or(0, |(args, _)| args);
+        self.call_stack.push(Frame {
+            return_address: self.ip + 1,
+            callee: target,
+            call_site: self.program[self.ip].pos(),
+            signature,
+            base: self.stack.len() - args,
+            locals: HashMap::new(),
+        });
+        self.ip = target;
+    }
+
+    /// The name of the subroutine `frame` is a call to.
+    fn callee(&self, frame: &Frame) -> &str {
+        label_at(&self.program, frame.callee)
+    }
 
-        // println!("Instructions:");
-        // for instruction in &self.program {
-        //     println!("{:?}", instruction);
-        // }
-        // println!("");
-        // println!("Labels:");
-        // for (label, ip) in &self.labels {
-        //     println!("{}: {}", label, ip);
-        // }
-        // println!("");
+    /// Local variables of the innermost `call`, or of the top level.
+    fn locals(&mut self) -> &mut HashMap<String, ConstType> {
+        let scope = self
+            .call_stack
+            .iter_mut()
+            .rev()
+            .find(|frame| frame.signature.is_some());
+        match scope {
+            Some(frame) => &mut frame.locals,
+            None => &mut self.locals,
+        }
+    }
+
+    fn local(&self, name: &str) -> Option<&ConstType> {
+        let scope = self
+            .call_stack
+            .iter()
+            .rev()
+            .find(|frame| frame.signature.is_some());
+        match scope {
+            Some(frame) => frame.locals.get(name),
+            None => self.locals.get(name),
+        }
+    }
+
+    /// Runs until the program ends or raises an error that no `try` block
+    /// catches.
+    fn run(&mut self) -> Result<(), Error> {
+        self.deadline = self.limits.time.map(|time| Instant::now() + time);
+        if let Some(tracer) = &mut self.tracer {
+            tracer.start(&self.program);
+        }
+        if let Some(coverage) = &mut self.coverage {
+            coverage.start(&self.program);
+        }
+
+        let result = loop {
+            match self.run_instructions() {
+                Err(error) if CATCHABLE.contains(&error.code) && !self.handlers.is_empty() => {
+                    self.catch(error);
+                }
+                result => break result,
+            }
+        };
 
+        if let Some(tracer) = &mut self.tracer {
+            tracer.flush();
+        }
+        result
+    }
+
+    /// Whether the tracer wants the current step. Decided before the step
+    /// runs, so a `ret` is traced as part of the call it leaves.
+    fn is_traced(&self) -> bool {
+        let Some(tracer) = &self.tracer else {
+            return false;
+        };
+        if !tracer.wants(self.steps) {
+            return false;
+        }
+        match tracer.label() {
+            Some(label) => self
+                .call_stack
+                .iter()
+                .any(|frame| self.callee(frame) == label),
+            None => true,
+        }
+    }
+
+    /// Counts the step that ran the instruction at `ip`, which started at
+    /// `started`.
+    fn profile_step(&mut self, ip: usize, started: Option<Instant>) {
+        if let (Some(profiler), Some(started)) = (&mut self.profiler, started) {
+            let pos = self.program[ip].pos();
+            profiler.record(pos.file, pos.line, started.elapsed());
+        }
+    }
+
+    /// Counts the step that ran the instruction at `ip`. A conditional jump
+    /// was taken if the step left it.
+    fn cover_step(&mut self, ip: usize) {
+        if let Some(coverage) = &mut self.coverage {
+            coverage.record(ip, self.ip != ip);
+        }
+    }
+
+    /// Records the step that just ran the instruction at `ip`.
+    fn trace_step(&mut self, ip: usize) {
+        if let Some(tracer) = &mut self.tracer {
+            tracer.record(self.steps, ip, &self.program[ip], &self.stack);
+        }
+    }
+
+    /// Unwinds the stack and the calls to the innermost `try` block and
+    /// continues at its handler with the error value on the stack: the
+    /// thrown value, or the message of a built-in error.
+    fn catch(&mut self, error: Error) {
+        let handler = self.handlers.last().expect("an active try block");
+        if let Some(change) = self.history.as_mut().and_then(History::last_mut) {
+            change.unwind(
+                (&self.stack, handler.depth),
+                (&self.call_stack, handler.calls),
+                (&self.handlers, self.handlers.len() - 1),
+            );
+        }
+
+        let handler = self.handlers.pop().expect("an active try block");
+        let value = self
+            .thrown
+            .take()
+            .unwrap_or(ConstType::String(error.message));
+
+        self.stack.truncate(handler.depth);
+        self.stack.push(value);
+        for frame in self.call_stack.drain(handler.calls..) {
+            self.variables -= frame_size(&frame);
+        }
+        self.ip = handler.target;
+    }
+
+    /// Counts the bytes held by the values in variables and in registers,
+    /// for when they were replaced wholesale.
+    fn variable_size(&self) -> usize {
+        let locals = self.locals.values().chain(self.registers.iter().flatten());
+        let frames: usize = self.call_stack.iter().map(frame_size).sum();
+        frames + locals.map(ConstType::size).sum::<usize>()
+    }
+
+    /// Bytes held by the values on the stack, in variables and in registers.
+    fn heap_size(&self) -> usize {
+        self.stack.bytes + self.variables
+    }
+
+    /// Checks the other resource limits after the instruction at `ip` ran.
+    fn check_limits(&self, ip: usize) -> Result<(), Error> {
+        let limits = &self.limits;
+        let exceeded = |what: &str, limit: String| {
+            let message = format!("{} limit exceeded: {}", what, limit);
+            Err(Error::new("E0215", &message, self.program[ip].pos()))
+        };
+
+        if let Some(limit) = limits.stack.filter(|limit| self.stack.len() > *limit) {
+            return exceeded("Stack", format!("more than {} values", limit));
+        }
+        if let Some(limit) = limits.calls.filter(|limit| self.call_stack.len() > *limit) {
+            return exceeded("Call depth", format!("more than {} calls", limit));
+        }
+        if let (Some(limit), Some(ConstType::String(s))) = (limits.string, self.stack.last()) {
+            if s.len() > limit {
+                return exceeded("String length", format!("more than {} bytes", limit));
+            }
+        }
+
+        if limits.heap.is_some() && allocates(&self.program[ip]) {
+            if let Some(limit) = limits.heap.filter(|limit| self.heap_size() > *limit) {
+                return exceeded("Heap", format!("more than {} bytes", limit));
+            }
+        }
+        if self.steps.is_multiple_of(CHECK_INTERVAL) {
+            if let (Some(time), Some(deadline)) = (limits.time, self.deadline) {
+                if Instant::now() > deadline {
+                    return exceeded("Time", format!("{} ms", time.as_millis()));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn run_instructions(&mut self) -> Result<(), Error> {
         while self.ip < self.program.len() {
-            // println!("IP: {}", self.ip);
-            // println!("Stack: {:?}", self.stack);
-            // println!("Return stack: {:?}", self.return_stack);
+            if !self.step()? {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs the instruction at `ip`, noting in the undo log how to undo it.
+    /// Returns `false` if it was `exit`.
+    fn step(&mut self) -> Result<bool, Error> {
+        if self.stack_dump > 0 {
+            let depth = self.stack.len().saturating_sub(MAX_POPPED);
+            self.before = Some((depth, self.stack[depth..].to_vec()));
+        }
+        if self.history.is_none() {
+            return self.instruction();
+        }
+
+        let mut change = self.change();
+        let result = self.instruction();
+        change.read = matches!(self.program[change.ip], Instruction::Read(_)) && result.is_ok();
+        if let Some(history) = &mut self.history {
+            history.push(change);
+        }
+        result
+    }
+
+    /// What the instruction at `ip` may change, as it is now.
+    fn change(&self) -> Change {
+        let depth = self.stack.len().saturating_sub(MAX_POPPED);
+        let mut change = Change {
+            ip: self.ip,
+            flags: self.flags.clone(),
+            depth,
+            values: self.stack[depth..].to_vec(),
+            calls: self.call_stack.len(),
+            frames: Vec::new(),
+            try_blocks: self.handlers.len(),
+            handlers: Vec::new(),
+            variable: None,
+            rng: None,
+            read: false,
+        };
+
+        match &self.program[self.ip] {
+            Instruction::Return(_) => {
+                // `ret` leaves the innermost call and the `try` blocks in it.
+                let calls = self.call_stack.len().saturating_sub(1);
+                let try_blocks = self
+                    .handlers
+                    .iter()
+                    .position(|handler| handler.calls > calls)
+                    .unwrap_or(self.handlers.len());
+                change.unwind(
+                    (&self.stack, depth),
+                    (&self.call_stack, calls),
+                    (&self.handlers, try_blocks),
+                );
+            }
+            Instruction::EndTry(_) => {
+                let try_blocks = self.handlers.len().saturating_sub(1);
+                change.unwind(
+                    (&self.stack, depth),
+                    (&self.call_stack, self.call_stack.len()),
+                    (&self.handlers, try_blocks),
+                );
+            }
+            Instruction::Set(variable, _) => {
+                let value = match variable {
+                    Variable::Local(name) => self.local(name),
+                    Variable::Register(index) => self.registers[*index].as_ref(),
+                };
+                change.variable = Some((variable.clone(), value.cloned()));
+            }
+            Instruction::Random(_)
+            | Instruction::RandomInt(_, _, _)
+            | Instruction::SeedRandom(_) => {
+                change.rng = Some(self.rng.clone());
+            }
+            _ => {}
+        }
+
+        change
+    }
+
+    /// Puts back what a step changed.
+    fn undo(&mut self, change: Change) {
+        if change.read {
+            if let Some(ConstType::String(line)) = self.stack.last() {
+                self.input.push(line.clone());
+            }
+        }
+
+        self.stack.truncate(change.depth);
+        self.stack.extend(change.values);
+        self.call_stack.truncate(change.calls);
+        self.call_stack.extend(change.frames);
+        self.handlers.truncate(change.try_blocks);
+        self.handlers.extend(change.handlers);
+        match change.variable {
+            Some((Variable::Local(name), Some(value))) => {
+                self.locals().insert(name, value);
+            }
+            Some((Variable::Local(name), None)) => {
+                self.locals().remove(&name);
+            }
+            Some((Variable::Register(index), value)) => self.registers[index] = value,
+            None => {}
+        }
+        self.variables = self.variable_size();
+        if let Some(rng) = change.rng {
+            self.rng = rng;
+        }
+        self.flags = change.flags;
+        self.ip = change.ip;
+        self.thrown = None;
+        self.steps -= 1;
+    }
+
+    /// Fails if the step about to run would go past the instruction limit,
+    /// so a limit of `n` runs exactly `n` instructions.
+    fn check_instructions(&self) -> Result<(), Error> {
+        match self.limits.instructions {
+            Some(limit) if self.steps > limit => {
+                let unit = if limit == 1 {
+                    "instruction"
+                } else {
+                    "instructions"
+                };
+                let message = format!("Instruction limit exceeded: {} {}", limit, unit);
+                Err(Error::new("E0215", &message, self.program[self.ip].pos()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Runs the instruction at `ip`. Returns `false` if it was `exit`.
+    fn instruction(&mut self) -> Result<bool, Error> {
+        let current = self.ip;
+        self.steps += 1;
+        self.check_instructions()?;
+        let traced = self.is_traced();
+        let started = match &mut self.profiler {
+            Some(profiler) => {
+                let program = &self.program;
+                profiler.enter(&self.call_stack, |frame| label_at(program, frame.callee));
+                Some(Instant::now())
+            }
+            None => None,
+        };
+
+        let result = self.dispatch();
+        // A failing instruction is recorded too, so a trace or a profile
+        // ends with the step that raised the error.
+        if traced {
+            self.trace_step(current);
+        }
+        self.profile_step(current, started);
+        self.cover_step(current);
+        if !result? {
+            return Ok(false);
+        }
+        // Past the instruction before the limits are checked, so a run
+        // stopped by a limit resumes with the next instruction.
+        self.ip += 1;
+        self.check_limits(current)?;
+
+        Ok(true)
+    }
+
+    /// Carries out the instruction at `ip`. Returns `false` if it was `exit`.
+    fn dispatch(&mut self) -> Result<bool, Error> {
+        match self.program[self.ip] {
+            Instruction::Push(ref value, _) => match value {
+                ConstType::Integer(i) => {
+                    self.stack.push(ConstType::Integer(*i));
+                }
+                ConstType::Float(f) => {
+                    self.stack.push(ConstType::Float(*f));
+                }
+                ConstType::String(ref s) => {
+                    self.stack.push(ConstType::String(s.clone()));
+                }
+            },
+            Instruction::Pop(ref pos) => {
+                if self.stack.is_empty() {
+                    // panic!("Cannot pop from an empty stack");
+                    return Err(Error::new("E0201", "Cannot pop from an empty stack", *pos));
+                }
+                self.stack.pop();
+            }
+            Instruction::Duplicate(ref pos) => {
+                if self.stack.is_empty() {
+                    // panic!("Cannot duplicate from an empty stack");
+                    return Err(Error::new(
+                        "E0201",
+                        "Cannot duplicate from an empty stack",
+                        *pos,
+                    ));
+                }
+
+                let value = self.stack.pop().unwrap();
+                self.stack.push(value.clone());
+                self.stack.push(value);
+            }
+            Instruction::Swap(ref pos) => {
+                if self.stack.len() < 2 {
+                    // panic!("Not enough operands for SWAP instruction");
+                    return Err(Error::new(
+                        "E0201",
+                        "Not enough operands for SWAP instruction",
+                        *pos,
+                    ));
+                }
+
+                let a = self.stack.pop().unwrap();
+                let b = self.stack.pop().unwrap();
 
-            match self.program[self.ip] {
-                Instruction::Push(ref value, ref pos) => match value {
+                self.stack.push(a);
+                self.stack.push(b);
+            }
+            Instruction::Set(ref variable, ref pos) => {
+                let Some(value) = self.stack.pop() else {
+                    return Err(Error::new(
+                        "E0201",
+                        &format!("Cannot set {} from an empty stack", variable),
+                        *pos,
+                    ));
+                };
+
+                self.variables += value.size();
+                let replaced = match variable {
+                    Variable::Local(name) => {
+                        let name = name.clone();
+                        self.locals().insert(name, value)
+                    }
+                    Variable::Register(index) => self.registers[*index].replace(value),
+                };
+                self.variables -= replaced.map_or(0, |value| value.size());
+            }
+            Instruction::Get(ref variable, ref pos) => {
+                let value = match variable {
+                    Variable::Local(name) => self.local(name),
+                    Variable::Register(index) => self.registers[*index].as_ref(),
+                };
+
+                match value {
+                    Some(value) => self.stack.push(value.clone()),
+                    None => {
+                        return Err(match variable {
+                            Variable::Local(name) => Error::new(
+                                "E0209",
+                                &format!("Variable `{}` is not set", name),
+                                *pos,
+                            )
+                            .with_help("local variables only exist in the `call` that sets them"),
+                            Variable::Register(index) => Error::new(
+                                "E0209",
+                                &format!("Register `r{}` is not set", index),
+                                *pos,
+                            ),
+                        });
+                    }
+                }
+            }
+            Instruction::Add(ref pos) => {
+                if self.stack.len() < 2 {
+                    // panic!("Not enough operands for ADD instruction");
+                    return Err(Error::new(
+                        "E0201",
+                        "Not enough operands for ADD instruction",
+                        *pos,
+                    ));
+                }
+
+                let a = self.stack.pop().unwrap();
+                let b = self.stack.pop().unwrap();
+
+                match (a, b) {
+                    (ConstType::Integer(a), ConstType::Integer(b)) => {
+                        let Some(value) = i32::checked_add(a, b) else {
+                            return Err(Error::new(
+                                "E0218",
+                                "Integer overflow in ADD instruction",
+                                *pos,
+                            ));
+                        };
+                        self.stack.push(ConstType::Integer(value));
+                    }
+                    (ConstType::Float(a), ConstType::Float(b)) => {
+                        self.stack.push(ConstType::Float(a + b));
+                    }
+                    (ConstType::Float(a), ConstType::Integer(b)) => {
+                        self.stack.push(ConstType::Float(a + b as f32))
+                    }
+                    (ConstType::Integer(a), ConstType::Float(b)) => {
+                        self.stack.push(ConstType::Float(a as f32 + b))
+                    }
+                    (ConstType::String(a), ConstType::String(b)) => {
+                        self.stack.push(ConstType::String(b + &a));
+                    }
+                    _ => {
+                        // panic!("Type mismatch for ADD instruction");
+                        return Err(Error::new(
+                            "E0202",
+                            "Type mismatch for ADD instruction",
+                            *pos,
+                        ));
+                    }
+                }
+            }
+            Instruction::Sub(ref pos) => {
+                if self.stack.len() < 2 {
+                    // panic!("Not enough operands for SUB instruction");
+                    return Err(Error::new(
+                        "E0201",
+                        "Not enough operands for SUB instruction",
+                        *pos,
+                    ));
+                }
+
+                let a = self.stack.pop().unwrap();
+                let b = self.stack.pop().unwrap();
+
+                match (a, b) {
+                    (ConstType::Integer(a), ConstType::Integer(b)) => {
+                        let Some(value) = i32::checked_sub(b, a) else {
+                            return Err(Error::new(
+                                "E0218",
+                                "Integer overflow in SUB instruction",
+                                *pos,
+                            ));
+                        };
+                        self.stack.push(ConstType::Integer(value));
+                    }
+                    (ConstType::Float(a), ConstType::Float(b)) => {
+                        self.stack.push(ConstType::Float(b - a));
+                    }
+                    (ConstType::Float(a), ConstType::Integer(b)) => {
+                        self.stack.push(ConstType::Float(b as f32 - a))
+                    }
+                    (ConstType::Integer(a), ConstType::Float(b)) => {
+                        self.stack.push(ConstType::Float(a as f32 - b))
+                    }
+                    _ => {
+                        // panic!("Type mismatch for SUB instruction");
+                        return Err(Error::new(
+                            "E0202",
+                            "Type mismatch for SUB instruction",
+                            *pos,
+                        ));
+                    }
+                }
+            }
+            Instruction::Mul(ref pos) => {
+                if self.stack.len() < 2 {
+                    // panic!("Not enough operands for MUL instruction");
+                    return Err(Error::new(
+                        "E0201",
+                        "Not enough operands for MUL instruction",
+                        *pos,
+                    ));
+                }
+
+                let a = self.stack.pop().unwrap();
+                let b = self.stack.pop().unwrap();
+
+                match (a, b) {
+                    (ConstType::Integer(a), ConstType::Integer(b)) => {
+                        let Some(value) = i32::checked_mul(a, b) else {
+                            return Err(Error::new(
+                                "E0218",
+                                "Integer overflow in MUL instruction",
+                                *pos,
+                            ));
+                        };
+                        self.stack.push(ConstType::Integer(value));
+                    }
+                    (ConstType::Float(a), ConstType::Float(b)) => {
+                        self.stack.push(ConstType::Float(a * b));
+                    }
+                    (ConstType::Float(a), ConstType::Integer(b)) => {
+                        self.stack.push(ConstType::Float(a * b as f32))
+                    }
+                    (ConstType::Integer(a), ConstType::Float(b)) => {
+                        self.stack.push(ConstType::Float(a as f32 * b))
+                    }
+                    _ => {
+                        // panic!("Type mismatch for MUL instruction");
+                        return Err(Error::new(
+                            "E0202",
+                            "Type mismatch for MUL instruction",
+                            *pos,
+                        ));
+                    }
+                }
+            }
+            Instruction::Div(ref pos) => {
+                if self.stack.len() < 2 {
+                    // panic!("Not enough operands for DIV instruction");
+                    return Err(Error::new(
+                        "E0201",
+                        "Not enough operands for DIV instruction",
+                        *pos,
+                    ));
+                }
+
+                let a = self.stack.pop().unwrap();
+                let b = self.stack.pop().unwrap();
+
+                match (a, b) {
+                    (ConstType::Integer(0), ConstType::Integer(_)) => {
+                        return Err(Error::new("E0212", "Division by zero", *pos));
+                    }
+                    (ConstType::Integer(a), ConstType::Integer(b)) => {
+                        let Some(value) = b.checked_div(a) else {
+                            return Err(Error::new(
+                                "E0218",
+                                "Integer overflow in DIV instruction",
+                                *pos,
+                            ));
+                        };
+                        self.stack.push(ConstType::Integer(value));
+                    }
+                    (ConstType::Float(a), ConstType::Float(b)) => {
+                        self.stack.push(ConstType::Float(b / a));
+                    }
+                    (ConstType::Float(a), ConstType::Integer(b)) => {
+                        self.stack.push(ConstType::Float(b as f32 / a))
+                    }
+                    (ConstType::Integer(a), ConstType::Float(b)) => {
+                        self.stack.push(ConstType::Float(b / a as f32))
+                    }
+                    _ => {
+                        // panic!("Type mismatch for DIV instruction");
+                        return Err(Error::new(
+                            "E0202",
+                            "Type mismatch for DIV instruction",
+                            *pos,
+                        ));
+                    }
+                }
+            }
+            Instruction::IDiv(ref pos) => {
+                if self.stack.len() < 2 {
+                    // panic!("Not enough operands for IDIV instruction");
+                    return Err(Error::new(
+                        "E0201",
+                        "Not enough operands for IDIV instruction",
+                        *pos,
+                    ));
+                }
+
+                let a = self.stack.pop().unwrap();
+                let b = self.stack.pop().unwrap();
+
+                match (a, b) {
+                    (ConstType::Integer(0), ConstType::Integer(_)) => {
+                        return Err(Error::new("E0212", "Division by zero", *pos));
+                    }
+                    (ConstType::Integer(a), ConstType::Integer(b)) => {
+                        let Some(value) = b.checked_div(a) else {
+                            return Err(Error::new(
+                                "E0218",
+                                "Integer overflow in IDIV instruction",
+                                *pos,
+                            ));
+                        };
+                        self.stack.push(ConstType::Integer(value));
+                    }
+                    (ConstType::Float(a), ConstType::Float(b)) => {
+                        self.stack.push(ConstType::Integer((b / a).floor() as i32));
+                    }
+                    (ConstType::Float(a), ConstType::Integer(b)) => self
+                        .stack
+                        .push(ConstType::Integer((b as f32 / a).floor() as i32)),
+                    (ConstType::Integer(a), ConstType::Float(b)) => self
+                        .stack
+                        .push(ConstType::Integer((b / a as f32).floor() as i32)),
+                    _ => {
+                        // panic!("Type mismatch for IDIV instruction");
+                        return Err(Error::new(
+                            "E0202",
+                            "Type mismatch for IDIV instruction",
+                            *pos,
+                        ));
+                    }
+                }
+            }
+            Instruction::Mod(ref pos) => {
+                if self.stack.len() < 2 {
+                    // panic!("Not enough operands for MOD instruction");
+                    return Err(Error::new(
+                        "E0201",
+                        "Not enough operands for MOD instruction",
+                        *pos,
+                    ));
+                }
+
+                let a = self.stack.pop().unwrap();
+                let b = self.stack.pop().unwrap();
+
+                match (a, b) {
+                    (ConstType::Integer(0), ConstType::Integer(_)) => {
+                        return Err(Error::new("E0212", "Division by zero", *pos));
+                    }
+                    (ConstType::Integer(a), ConstType::Integer(b)) => {
+                        let Some(value) = b.checked_rem(a) else {
+                            return Err(Error::new(
+                                "E0218",
+                                "Integer overflow in MOD instruction",
+                                *pos,
+                            ));
+                        };
+                        self.stack.push(ConstType::Integer(value));
+                    }
+                    (ConstType::Float(a), ConstType::Float(b)) => {
+                        self.stack.push(ConstType::Float(b % a));
+                    }
+                    (ConstType::Float(a), ConstType::Integer(b)) => {
+                        self.stack.push(ConstType::Float(b as f32 % a))
+                    }
+                    (ConstType::Integer(a), ConstType::Float(b)) => {
+                        self.stack.push(ConstType::Float(b % a as f32))
+                    }
+                    _ => {
+                        // panic!("Type mismatch for MOD instruction");
+                        return Err(Error::new(
+                            "E0202",
+                            "Type mismatch for MOD instruction",
+                            *pos,
+                        ));
+                    }
+                }
+            }
+            Instruction::Assert(ref pos) => {
+                if self.stack.is_empty() {
+                    // panic!("Not enough operands for ASSERT instruction");
+                    return Err(Error::new(
+                        "E0201",
+                        "Not enough operands for ASSERT instruction",
+                        *pos,
+                    ));
+                }
+
+                let value = self.stack.pop().unwrap();
+                if !value.is_truthy() {
+                    return Err(Error::new(
+                        "E0207",
+                        &format!("Assertion failed: {} is falsy", value),
+                        *pos,
+                    ));
+                }
+            }
+            Instruction::AssertEq(ref message, ref pos) => {
+                if self.stack.len() < 2 {
+                    // panic!("Not enough operands for ASSERT_EQ instruction");
+                    return Err(Error::new(
+                        "E0201",
+                        "Not enough operands for ASSERT_EQ instruction",
+                        *pos,
+                    ));
+                }
+
+                let a = self.stack.pop().unwrap();
+                let b = self.stack.pop().unwrap();
+                if a != b {
+                    return Err(Error::new(
+                        "E0207",
+                        &format!("Assertion failed: {} (left: {}, right: {})", message, b, a),
+                        *pos,
+                    ));
+                }
+            }
+            Instruction::Print(ref pos) => {
+                if self.stack.is_empty() {
+                    // panic!("Cannot print from an empty stack");
+                    return Err(Error::new(
+                        "E0201",
+                        "Cannot print from an empty stack",
+                        *pos,
+                    ));
+                }
+
+                let value = self.stack.pop().unwrap();
+                match value {
                     ConstType::Integer(i) => {
-                        self.stack.push(ConstType::Integer(*i));
-                        // println!("[DEBUG] Pushed {:?}", value);
+                        println!("{}", i);
                     }
                     ConstType::Float(f) => {
-                        self.stack.push(ConstType::Float(*f));
-                        // println!("[DEBUG] Pushed {:?}", value);
+                        println!("{}", f);
+                    }
+                    ConstType::String(s) => {
+                        println!("{}", s);
+                    }
+                }
+            }
+            Instruction::Read(ref pos) => {
+                if self.sandbox {
+                    return Err(Error::new("E0216", "Reading input is disabled", *pos)
+                        .with_help("the program runs in a sandbox"));
+                }
+                let mut input = String::new();
+                // Lines read by undone steps are read again first.
+                let read = match self.input.pop() {
+                    Some(line) => {
+                        input = line;
+                        Ok(input.len() + 1)
                     }
-                    ConstType::String(ref s) => {
-                        self.stack.push(ConstType::String(s.clone()));
-                        // println!("[DEBUG] Pushed {:?}", value);
+                    None => io::stdin().read_line(&mut input),
+                };
+                match read {
+                    // Not catchable, so a loop that retries on bad input
+                    // ends when the input does.
+                    Ok(0) => {
+                        return Err(Error::new("E0214", "Unexpected end of input", *pos));
                     }
-                },
-                Instruction::Pop(ref pos) => {
-                    if self.stack.is_empty() {
-                        // panic!("Cannot pop from an empty stack");
-                        Error::new("Cannot pop from an empty stack", pos.clone()).print();
-                        return;
+                    Ok(_) => {
+                        self.stack.push(ConstType::String(input.trim().to_string()));
                     }
-                    self.stack.pop();
-                }
-                Instruction::Duplicate(ref pos) => {
-                    if self.stack.is_empty() {
-                        // panic!("Cannot duplicate from an empty stack");
-                        Error::new("Cannot duplicate from an empty stack", pos.clone()).print();
-                        return;
+                    Err(_) => {
+                        // panic!("Failed to read input");
+                        return Err(Error::new("E0205", "Failed to read input", *pos));
                     }
-
-                    let value = self.stack.pop().unwrap();
-                    self.stack.push(value.clone());
-                    self.stack.push(value);
                 }
-                Instruction::Swap(ref pos) => {
-                    if self.stack.len() < 2 {
-                        // panic!("Not enough operands for SWAP instruction");
-                        Error::new("Not enough operands for SWAP instruction", pos.clone()).print();
-                        return;
-                    }
-
-                    let a = self.stack.pop().unwrap();
-                    let b = self.stack.pop().unwrap();
-
-                    self.stack.push(a);
-                    self.stack.push(b);
+            }
+            Instruction::AToI(ref pos) => {
+                if self.stack.is_empty() {
+                    // panic!("Cannot convert emptiness to an integer!");
+                    return Err(Error::new(
+                        "E0201",
+                        "Cannot convert emptiness to an integer!",
+                        *pos,
+                    ));
                 }
-                Instruction::Add(ref pos) => {
-                    if self.stack.len() < 2 {
-                        // panic!("Not enough operands for ADD instruction");
-                        Error::new("Not enough operands for ADD instruction", pos.clone()).print();
-                        return;
-                    }
 
-                    let a = self.stack.pop().unwrap();
-                    let b = self.stack.pop().unwrap();
-
-                    match (a, b) {
-                        (ConstType::Integer(a), ConstType::Integer(b)) => {
-                            self.stack.push(ConstType::Integer(a + b));
-                        }
-                        (ConstType::Float(a), ConstType::Float(b)) => {
-                            self.stack.push(ConstType::Float(a + b));
+                let value = self.stack.pop().unwrap();
+                match value {
+                    ConstType::String(s) => match s.parse::<i32>() {
+                        Ok(i) => {
+                            self.stack.push(ConstType::Integer(i));
                         }
-                        (ConstType::Float(a), ConstType::Integer(b)) => {
-                            self.stack.push(ConstType::Float(a + b as f32))
-                        }
-                        (ConstType::Integer(a), ConstType::Float(b)) => {
-                            self.stack.push(ConstType::Float(a as f32 + b))
-                        }
-                        (ConstType::String(a), ConstType::String(b)) => {
-                            self.stack.push(ConstType::String(b + &a));
-                        }
-                        _ => {
-                            // panic!("Type mismatch for ADD instruction");
-                            Error::new("Type mismatch for ADD instruction", pos.clone()).print();
-                            return;
+                        Err(_) => {
+                            // panic!("Invalid integer: {}", s);
+                            return Err(Error::new(
+                                "E0203",
+                                &format!("Invalid integer: {}", s),
+                                *pos,
+                            ));
                         }
+                    },
+                    _ => {
+                        // panic!("Type mismatch for ATOI instruction");
+                        return Err(Error::new(
+                            "E0202",
+                            "Type mismatch for ATOI instruction",
+                            *pos,
+                        ));
                     }
                 }
-                Instruction::Sub(ref pos) => {
-                    if self.stack.len() < 2 {
-                        // panic!("Not enough operands for SUB instruction");
-                        Error::new("Not enough operands for SUB instruction", pos.clone()).print();
-                        return;
-                    }
-
-                    let a = self.stack.pop().unwrap();
-                    let b = self.stack.pop().unwrap();
+            }
+            Instruction::FToI(ref pos) => {
+                if self.stack.is_empty() {
+                    // panic!("Cannot convert emptiness to an integer!");
+                    return Err(Error::new(
+                        "E0201",
+                        "Cannot convert emptiness to an integer!",
+                        *pos,
+                    ));
+                }
 
-                    match (a, b) {
-                        (ConstType::Integer(a), ConstType::Integer(b)) => {
-                            self.stack.push(ConstType::Integer(b - a));
-                        }
-                        (ConstType::Float(a), ConstType::Float(b)) => {
-                            self.stack.push(ConstType::Float(b - a));
-                        }
-                        (ConstType::Float(a), ConstType::Integer(b)) => {
-                            self.stack.push(ConstType::Float(b as f32 - a))
-                        }
-                        (ConstType::Integer(a), ConstType::Float(b)) => {
-                            self.stack.push(ConstType::Float(a as f32 - b))
-                        }
-                        _ => {
-                            // panic!("Type mismatch for SUB instruction");
-                            Error::new("Type mismatch for SUB instruction", pos.clone()).print();
-                            return;
-                        }
+                let value = self.stack.pop().unwrap();
+                match value {
+                    ConstType::Float(f) => {
+                        self.stack.push(ConstType::Integer(f as i32));
                     }
-                }
-                Instruction::Mul(ref pos) => {
-                    if self.stack.len() < 2 {
-                        // panic!("Not enough operands for MUL instruction");
-                        Error::new("Not enough operands for MUL instruction", pos.clone()).print();
-                        return;
+                    _ => {
+                        // panic!("Type mismatch for FTOI instruction");
+                        return Err(Error::new(
+                            "E0202",
+                            "Type mismatch for FTOI instruction",
+                            *pos,
+                        ));
                     }
-
-                    let a = self.stack.pop().unwrap();
-                    let b = self.stack.pop().unwrap();
-
-                    match (a, b) {
-                        (ConstType::Integer(a), ConstType::Integer(b)) => {
-                            self.stack.push(ConstType::Integer(a * b));
-                        }
-                        (ConstType::Float(a), ConstType::Float(b)) => {
-                            self.stack.push(ConstType::Float(a * b));
-                        }
-                        (ConstType::Float(a), ConstType::Integer(b)) => {
-                            self.stack.push(ConstType::Float(a * b as f32))
-                        }
-                        (ConstType::Integer(a), ConstType::Float(b)) => {
-                            self.stack.push(ConstType::Float(a as f32 * b))
+                }
+            }
+            Instruction::Jump(ref label, ref pos) => match self.labels.get(label) {
+                Some(ip) => {
+                    self.call(*ip, None);
+                }
+                None => {
+                    // panic!("Unknown label: {}", label);
+                    return Err(self.unknown_label(label, *pos));
+                }
+            },
+            Instruction::JumpEq(ref label, ref pos) => {
+                if self.flags.equal {
+                    match self.labels.get(label) {
+                        Some(ip) => {
+                            self.call(*ip, None);
                         }
-                        _ => {
-                            // panic!("Type mismatch for MUL instruction");
-                            Error::new("Type mismatch for MUL instruction", pos.clone()).print();
-                            return;
+                        None => {
+                            // panic!("Unknown label: {}", label);
+                            return Err(self.unknown_label(label, *pos));
                         }
                     }
                 }
-                Instruction::Div(ref pos) => {
-                    if self.stack.len() < 2 {
-                        // panic!("Not enough operands for DIV instruction");
-                        Error::new("Not enough operands for DIV instruction", pos.clone()).print();
-                        return;
-                    }
-
-                    let a = self.stack.pop().unwrap();
-                    let b = self.stack.pop().unwrap();
-
-                    match (a, b) {
-                        (ConstType::Integer(a), ConstType::Integer(b)) => {
-                            self.stack.push(ConstType::Integer(b / a));
-                        }
-                        (ConstType::Float(a), ConstType::Float(b)) => {
-                            self.stack.push(ConstType::Float(b / a));
-                        }
-                        (ConstType::Float(a), ConstType::Integer(b)) => {
-                            self.stack.push(ConstType::Float(b as f32 / a))
-                        }
-                        (ConstType::Integer(a), ConstType::Float(b)) => {
-                            self.stack.push(ConstType::Float(b / a as f32))
+            }
+            Instruction::JumpNotEq(ref label, ref pos) => {
+                if self.flags.not_equal {
+                    match self.labels.get(label) {
+                        Some(ip) => {
+                            self.call(*ip, None);
                         }
-                        _ => {
-                            // panic!("Type mismatch for DIV instruction");
-                            Error::new("Type mismatch for DIV instruction", pos.clone()).print();
-                            return;
+                        None => {
+                            // panic!("Unknown label: {}", label);
+                            return Err(self.unknown_label(label, *pos));
                         }
                     }
                 }
-                Instruction::IDiv(ref pos) => {
-                    if self.stack.len() < 2 {
-                        // panic!("Not enough operands for IDIV instruction");
-                        Error::new("Not enough operands for IDIV instruction", pos.clone()).print();
-                        return;
-                    }
-
-                    let a = self.stack.pop().unwrap();
-                    let b = self.stack.pop().unwrap();
-
-                    match (a, b) {
-                        (ConstType::Integer(a), ConstType::Integer(b)) => {
-                            self.stack.push(ConstType::Integer(b / a));
-                        }
-                        (ConstType::Float(a), ConstType::Float(b)) => {
-                            self.stack.push(ConstType::Integer((b / a).floor() as i32));
+            }
+            Instruction::JumpGt(ref label, ref pos) => {
+                if self.flags.greater_than {
+                    match self.labels.get(label) {
+                        Some(ip) => {
+                            self.call(*ip, None);
                         }
-                        (ConstType::Float(a), ConstType::Integer(b)) => self
-                            .stack
-                            .push(ConstType::Integer((b as f32 / a).floor() as i32)),
-                        (ConstType::Integer(a), ConstType::Float(b)) => self
-                            .stack
-                            .push(ConstType::Integer((b / a as f32).floor() as i32)),
-                        _ => {
-                            // panic!("Type mismatch for IDIV instruction");
-                            Error::new("Type mismatch for IDIV instruction", pos.clone()).print();
-                            return;
+                        None => {
+                            // panic!("Unknown label: {}", label);
+                            return Err(self.unknown_label(label, *pos));
                         }
                     }
                 }
-                Instruction::Mod(ref pos) => {
-                    if self.stack.len() < 2 {
-                        // panic!("Not enough operands for MOD instruction");
-                        Error::new("Not enough operands for MOD instruction", pos.clone()).print();
-                        return;
-                    }
-
-                    let a = self.stack.pop().unwrap();
-                    let b = self.stack.pop().unwrap();
-
-                    match (a, b) {
-                        (ConstType::Integer(a), ConstType::Integer(b)) => {
-                            self.stack.push(ConstType::Integer(b % a));
-                        }
-                        (ConstType::Float(a), ConstType::Float(b)) => {
-                            self.stack.push(ConstType::Float(b % a));
-                        }
-                        (ConstType::Float(a), ConstType::Integer(b)) => {
-                            self.stack.push(ConstType::Float(b as f32 % a))
-                        }
-                        (ConstType::Integer(a), ConstType::Float(b)) => {
-                            self.stack.push(ConstType::Float(b % a as f32))
+            }
+            Instruction::JumpLt(ref label, ref pos) => {
+                if self.flags.less_than {
+                    match self.labels.get(label) {
+                        Some(ip) => {
+                            self.call(*ip, None);
                         }
-                        _ => {
-                            // panic!("Type mismatch for MOD instruction");
-                            Error::new("Type mismatch for MOD instruction", pos.clone()).print();
-                            return;
+                        None => {
+                            // panic!("Unknown label: {}", label);
+                            return Err(self.unknown_label(label, *pos));
                         }
                     }
                 }
-                Instruction::Print(ref pos) => {
-                    if self.stack.is_empty() {
-                        // panic!("Cannot print from an empty stack");
-                        Error::new("Cannot print from an empty stack", pos.clone()).print();
-                        return;
-                    }
-
-                    let value = self.stack.pop().unwrap();
-                    match value {
-                        ConstType::Integer(i) => {
-                            println!("{}", i);
-                        }
-                        ConstType::Float(f) => {
-                            println!("{}", f);
+            }
+            Instruction::JumpGtEq(ref label, ref pos) => {
+                if self.flags.greater_than_or_equal {
+                    match self.labels.get(label) {
+                        Some(ip) => {
+                            self.call(*ip, None);
                         }
-                        ConstType::String(s) => {
-                            println!("{}", s);
+                        None => {
+                            // panic!("Unknown label: {}", label);
+                            return Err(self.unknown_label(label, *pos));
                         }
                     }
                 }
-                Instruction::Read(ref pos) => {
-                    let mut input = String::new();
-                    match io::stdin().read_line(&mut input) {
-                        Ok(_) => {
-                            self.stack.push(ConstType::String(input.trim().to_string()));
+            }
+            Instruction::JumpLtEq(ref label, ref pos) => {
+                if self.flags.less_than_or_equal {
+                    match self.labels.get(label) {
+                        Some(ip) => {
+                            self.call(*ip, None);
                         }
-                        Err(_) => {
-                            // panic!("Failed to read input");
-                            Error::new("Failed to read input", pos.clone()).print();
-                            return;
+                        None => {
+                            // panic!("Unknown label: {}", label);
+                            return Err(self.unknown_label(label, *pos));
                         }
                     }
                 }
-                Instruction::AToI(ref pos) => {
-                    if self.stack.is_empty() {
-                        // panic!("Cannot convert emptiness to an integer!");
-                        Error::new("Cannot convert emptiness to an integer!", pos.clone()).print();
-                        return;
-                    }
-
-                    let value = self.stack.pop().unwrap();
-                    match value {
-                        ConstType::String(s) => match s.parse::<i32>() {
-                            Ok(i) => {
-                                self.stack.push(ConstType::Integer(i));
-                            }
-                            Err(_) => {
-                                // panic!("Invalid integer: {}", s);
-                                Error::new(&format!("Invalid integer: {}", s), pos.clone()).print();
-                                return;
-                            }
-                        },
-                        _ => {
-                            // panic!("Type mismatch for ATOI instruction");
-                            Error::new("Type mismatch for ATOI instruction", pos.clone()).print();
-                            return;
+            }
+            Instruction::JumpZero(ref label, ref pos) => {
+                if self.flags.zero {
+                    match self.labels.get(label) {
+                        Some(ip) => {
+                            self.call(*ip, None);
+                        }
+                        None => {
+                            // panic!("Unknown label: {}", label);
+                            return Err(self.unknown_label(label, *pos));
                         }
                     }
                 }
-                Instruction::FToI(ref pos) => {
-                    if self.stack.is_empty() {
-                        // panic!("Cannot convert emptiness to an integer!");
-                        Error::new("Cannot convert emptiness to an integer!", pos.clone()).print();
-                        return;
-                    }
-
-                    let value = self.stack.pop().unwrap();
-                    match value {
-                        ConstType::Float(f) => {
-                            self.stack.push(ConstType::Integer(f as i32));
+            }
+            Instruction::JumpNotZero(ref label, ref pos) => {
+                if !self.flags.zero {
+                    match self.labels.get(label) {
+                        Some(ip) => {
+                            self.call(*ip, None);
                         }
-                        _ => {
-                            // panic!("Type mismatch for FTOI instruction");
-                            Error::new("Type mismatch for FTOI instruction", pos.clone()).print();
-                            return;
+                        None => {
+                            // panic!("Unknown label: {}", label);
+                            return Err(self.unknown_label(label, *pos));
                         }
                     }
                 }
-                Instruction::Jump(ref label, ref pos) => match self.labels.get(label) {
-                    Some(ip) => {
-                        self.return_stack.push(self.ip + 1);
-                        self.ip = *ip;
-                    }
-                    None => {
-                        // panic!("Unknown label: {}", label);
-                        Error::new(&format!("Unknown label: {}", label), pos.clone()).print();
-                        return;
-                    }
-                },
-                Instruction::JumpEq(ref label, ref pos) => {
-                    if self.flags.equal {
-                        match self.labels.get(label) {
-                            Some(ip) => {
-                                self.return_stack.push(self.ip + 1);
-                                self.ip = *ip;
-                            }
-                            None => {
-                                // panic!("Unknown label: {}", label);
-                                Error::new(&format!("Unknown label: {}", label), pos.clone())
-                                    .print();
-                            }
+            }
+            Instruction::JumpNeg(ref label, ref pos) => {
+                if self.flags.negative {
+                    match self.labels.get(label) {
+                        Some(ip) => {
+                            self.call(*ip, None);
                         }
-                    }
-                }
-                Instruction::JumpNotEq(ref label, ref pos) => {
-                    if self.flags.not_equal {
-                        match self.labels.get(label) {
-                            Some(ip) => {
-                                self.return_stack.push(self.ip + 1);
-                                self.ip = *ip;
-                            }
-                            None => {
-                                // panic!("Unknown label: {}", label);
-                                Error::new(&format!("Unknown label: {}", label), pos.clone())
-                                    .print();
-                                return;
-                            }
+                        None => {
+                            // panic!("Unknown label: {}", label);
+                            return Err(self.unknown_label(label, *pos));
                         }
                     }
                 }
-                Instruction::JumpGt(ref label, ref pos) => {
-                    if self.flags.greater_than {
-                        match self.labels.get(label) {
-                            Some(ip) => {
-                                self.return_stack.push(self.ip + 1);
-                                self.ip = *ip;
-                            }
-                            None => {
-                                // panic!("Unknown label: {}", label);
-                                Error::new(&format!("Unknown label: {}", label), pos.clone())
-                                    .print();
-                                return;
-                            }
-                        }
-                    }
+            }
+            Instruction::Call(ref label, args, results, ref pos) => {
+                if self.stack.len() < args {
+                    let message = format!(
+                        "Not enough arguments for call to `{}`: it takes {} but the stack holds {}",
+                        label,
+                        args,
+                        self.stack.len()
+                    );
+                    return Err(Error::new("E0201", &message, *pos));
                 }
-                Instruction::JumpLt(ref label, ref pos) => {
-                    if self.flags.less_than {
-                        match self.labels.get(label) {
-                            Some(ip) => {
-                                self.return_stack.push(self.ip + 1);
-                                self.ip = *ip;
-                            }
-                            None => {
-                                // panic!("Unknown label: {}", label);
-                                Error::new(&format!("Unknown label: {}", label), pos.clone())
-                                    .print();
-                                return;
-                            }
-                        }
-                    }
+                match self.labels.get(label) {
+                    Some(ip) => self.call(*ip, Some((args, results))),
+                    None => return Err(self.unknown_label(label, *pos)),
                 }
-                Instruction::JumpGtEq(ref label, ref pos) => {
-                    if self.flags.greater_than_or_equal {
-                        match self.labels.get(label) {
-                            Some(ip) => {
-                                self.return_stack.push(self.ip + 1);
-                                self.ip = *ip;
-                            }
-                            None => {
-                                // panic!("Unknown label: {}", label);
-                                Error::new(&format!("Unknown label: {}", label), pos.clone())
-                                    .print();
-                                return;
-                            }
+            }
+            Instruction::Return(ref pos) => {
+                // Checked while the call is still active, so the stack trace
+                // of the error includes it.
+                if let Some(frame) = self.call_stack.last() {
+                    if let Some((args, results)) = frame.signature {
+                        if self.stack.len() != frame.base + results {
+                            return Err(self.wrong_results(frame, args, results, *pos));
                         }
                     }
                 }
-                Instruction::JumpLtEq(ref label, ref pos) => {
-                    if self.flags.less_than_or_equal {
-                        match self.labels.get(label) {
-                            Some(ip) => {
-                                self.return_stack.push(self.ip + 1);
-                                self.ip = *ip;
-                            }
-                            None => {
-                                // panic!("Unknown label: {}", label);
-                                Error::new(&format!("Unknown label: {}", label), pos.clone())
-                                    .print();
-                                return;
-                            }
-                        }
+                if let Some(frame) = self.call_stack.pop() {
+                    self.variables -= frame_size(&frame);
+                    // A `try` block does not outlive its subroutine.
+                    while self
+                        .handlers
+                        .last()
+                        .is_some_and(|handler| handler.calls > self.call_stack.len())
+                    {
+                        self.handlers.pop();
                     }
+                    self.ip = frame.return_address - 1;
+                } else {
+                    // panic!("Cannot return anything from the main function!");
+                    return Err(Error::new(
+                        "E0204",
+                        "Cannot return anything from the main function!",
+                        *pos,
+                    ));
                 }
-                Instruction::JumpZero(ref label, ref pos) => {
-                    if self.flags.zero {
-                        match self.labels.get(label) {
-                            Some(ip) => {
-                                self.return_stack.push(self.ip + 1);
-                                self.ip = *ip;
-                            }
-                            None => {
-                                // panic!("Unknown label: {}", label);
-                                Error::new(&format!("Unknown label: {}", label), pos.clone())
-                                    .print();
-                                return;
-                            }
-                        }
-                    }
+            }
+            Instruction::Exit(_) => return Ok(false),
+            Instruction::Try(ref label, ref pos) => match self.labels.get(label) {
+                Some(ip) => self.handlers.push(Handler {
+                    target: *ip,
+                    depth: self.stack.len(),
+                    calls: self.call_stack.len(),
+                }),
+                None => return Err(self.unknown_label(label, *pos)),
+            },
+            Instruction::EndTry(ref pos) => {
+                if self.handlers.pop().is_none() {
+                    return Err(Error::new(
+                        "E0213",
+                        "`endtry` without a matching `try`",
+                        *pos,
+                    ));
                 }
-                Instruction::JumpNotZero(ref label, ref pos) => {
-                    if !self.flags.zero {
-                        match self.labels.get(label) {
-                            Some(ip) => {
-                                self.return_stack.push(self.ip + 1);
-                                self.ip = *ip;
-                            }
-                            None => {
-                                // panic!("Unknown label: {}", label);
-                                Error::new(&format!("Unknown label: {}", label), pos.clone())
-                                    .print();
-                            }
-                        }
+            }
+            Instruction::Throw(ref pos) => {
+                let Some(value) = self.stack.pop() else {
+                    return Err(Error::new(
+                        "E0201",
+                        "Cannot throw from an empty stack",
+                        *pos,
+                    ));
+                };
+                let error = Error::new("E0211", &format!("Uncaught exception: {}", value), *pos)
+                    .with_help("catch exceptions with a `try @handler` block");
+                self.thrown = Some(value);
+                return Err(error);
+            }
+            Instruction::Cmp(ref value, ref pos) => {
+                let Some(a) = self.stack.pop() else {
+                    return Err(Error::new(
+                        "E0201",
+                        "Not enough operands for CMP instruction",
+                        *pos,
+                    ));
+                };
+
+                match (&a, value) {
+                    (ConstType::Integer(a), ConstType::Integer(b)) => {
+                        self.flags.zero = *a == *b;
+                        self.flags.negative = *a < *b;
+                        self.flags.equal = *a == *b;
+                        self.flags.not_equal = *a != *b;
+                        self.flags.greater_than = *a > *b;
+                        self.flags.less_than = *a < *b;
+                        self.flags.greater_than_or_equal = *a >= *b;
+                        self.flags.less_than_or_equal = *a <= *b;
                     }
-                }
-                Instruction::JumpNeg(ref label, ref pos) => {
-                    if self.flags.negative {
-                        match self.labels.get(label) {
-                            Some(ip) => {
-                                self.return_stack.push(self.ip + 1);
-                                self.ip = *ip;
-                            }
-                            None => {
-                                // panic!("Unknown label: {}", label);
-                                Error::new(&format!("Unknown label: {}", label), pos.clone())
-                                    .print();
-                            }
-                        }
+                    (ConstType::Float(a), ConstType::Float(b)) => {
+                        self.flags.zero = *a == *b;
+                        self.flags.negative = *a < *b;
+                        self.flags.equal = *a == *b;
+                        self.flags.not_equal = *a != *b;
+                        self.flags.greater_than = *a > *b;
+                        self.flags.less_than = *a < *b;
+                        self.flags.greater_than_or_equal = *a >= *b;
+                        self.flags.less_than_or_equal = *a <= *b;
                     }
-                }
-                Instruction::Return(ref pos) => {
-                    if let Some(ip) = self.return_stack.pop() {
-                        self.ip = ip - 1;
-                    } else {
-                        // panic!("Cannot return anything from the main function!");
-                        Error::new(
-                            "Cannot return anything from the main function!",
-                            pos.clone(),
-                        )
-                        .print();
-                        return;
-                    }
-                }
-                Instruction::Exit(ref pos) => {
-                    return;
-                }
-                Instruction::Cmp(ref value, ref pos) => {
-                    let a = self.stack.pop().unwrap();
-
-                    match (&a, value) {
-                        (ConstType::Integer(a), ConstType::Integer(b)) => {
-                            self.flags.zero = *a == *b;
-                            self.flags.negative = *a < *b;
-                            self.flags.equal = *a == *b;
-                            self.flags.not_equal = *a != *b;
-                            self.flags.greater_than = *a > *b;
-                            self.flags.less_than = *a < *b;
-                            self.flags.greater_than_or_equal = *a >= *b;
-                            self.flags.less_than_or_equal = *a <= *b;
-                        }
-                        (ConstType::Float(a), ConstType::Float(b)) => {
-                            self.flags.zero = *a == *b;
-                            self.flags.negative = *a < *b;
-                            self.flags.equal = *a == *b;
-                            self.flags.not_equal = *a != *b;
-                            self.flags.greater_than = *a > *b;
-                            self.flags.less_than = *a < *b;
-                            self.flags.greater_than_or_equal = *a >= *b;
-                            self.flags.less_than_or_equal = *a <= *b;
-                        }
-                        (ConstType::String(a), ConstType::String(b)) => {
-                            self.flags.zero = *a == *b;
-                            self.flags.negative = *a < *b;
-                            self.flags.equal = *a == *b;
-                            self.flags.not_equal = *a != *b;
-                            self.flags.greater_than = *a > *b;
-                            self.flags.less_than = *a < *b;
-                            self.flags.greater_than_or_equal = *a >= *b;
-                            self.flags.less_than_or_equal = *a <= *b;
-                        }
-                        _ => {
-                            // panic!("Type mismatch for CMP instruction");
-                            Error::new("Type mismatch for CMP instruction", pos.clone()).print();
-                            return;
-                        }
-                    }
-
-                    self.stack.push(a);
-                }
-                Instruction::CmpInStack(ref pos) => {
-                    if self.stack.len() < 2 {
-                        // panic!("Not enough operands for SCMP instruction");
-                        Error::new("Not enough operands for SCMP instruction", pos.clone()).print();
-                        return;
-                    }
-
-                    let a = self.stack.pop().unwrap();
-                    let b = self.stack.pop().unwrap();
-
-                    match (&a, &b) {
-                        (ConstType::Integer(a), ConstType::Integer(b)) => {
-                            self.flags.zero = *a == *b;
-                            self.flags.negative = *a < *b;
-                            self.flags.equal = *a == *b;
-                            self.flags.not_equal = *a != *b;
-                            self.flags.greater_than = *a > *b;
-                            self.flags.less_than = *a < *b;
-                            self.flags.greater_than_or_equal = *a >= *b;
-                            self.flags.less_than_or_equal = *a <= *b;
-                        }
-                        (ConstType::Float(a), ConstType::Float(b)) => {
-                            self.flags.zero = *a == *b;
-                            self.flags.negative = *a < *b;
-                            self.flags.equal = *a == *b;
-                            self.flags.not_equal = *a != *b;
-                            self.flags.greater_than = *a > *b;
-                            self.flags.less_than = *a < *b;
-                            self.flags.greater_than_or_equal = *a >= *b;
-                            self.flags.less_than_or_equal = *a <= *b;
-                        }
-                        (ConstType::String(a), ConstType::String(b)) => {
-                            self.flags.zero = *a == *b;
-                            self.flags.negative = *a < *b;
-                            self.flags.equal = *a == *b;
-                            self.flags.not_equal = *a != *b;
-                            self.flags.greater_than = *a > *b;
-                            self.flags.less_than = *a < *b;
-                            self.flags.greater_than_or_equal = *a >= *b;
-                            self.flags.less_than_or_equal = *a <= *b;
-                        }
-                        _ => {
-                            // panic!("Type mismatch for SCMP instruction");
-                            Error::new("Type mismatch for SCMP instruction", pos.clone()).print();
-                            return;
-                        }
+                    (ConstType::String(a), ConstType::String(b)) => {
+                        self.flags.zero = *a == *b;
+                        self.flags.negative = *a < *b;
+                        self.flags.equal = *a == *b;
+                        self.flags.not_equal = *a != *b;
+                        self.flags.greater_than = *a > *b;
+                        self.flags.less_than = *a < *b;
+                        self.flags.greater_than_or_equal = *a >= *b;
+                        self.flags.less_than_or_equal = *a <= *b;
                     }
+                    _ => {
+                        // panic!("Type mismatch for CMP instruction");
+                        return Err(Error::new(
+                            "E0202",
+                            "Type mismatch for CMP instruction",
+                            *pos,
+                        ));
+                    }
+                }
+
+                self.stack.push(a);
+            }
+            Instruction::CmpInStack(ref pos) => {
+                if self.stack.len() < 2 {
+                    // panic!("Not enough operands for SCMP instruction");
+                    return Err(Error::new(
+                        "E0201",
+                        "Not enough operands for SCMP instruction",
+                        *pos,
+                    ));
+                }
+
+                let a = self.stack.pop().unwrap();
+                let b = self.stack.pop().unwrap();
 
-                    self.stack.push(b);
-                    self.stack.push(a);
+                match (&a, &b) {
+                    (ConstType::Integer(a), ConstType::Integer(b)) => {
+                        self.flags.zero = *a == *b;
+                        self.flags.negative = *a < *b;
+                        self.flags.equal = *a == *b;
+                        self.flags.not_equal = *a != *b;
+                        self.flags.greater_than = *a > *b;
+                        self.flags.less_than = *a < *b;
+                        self.flags.greater_than_or_equal = *a >= *b;
+                        self.flags.less_than_or_equal = *a <= *b;
+                    }
+                    (ConstType::Float(a), ConstType::Float(b)) => {
+                        self.flags.zero = *a == *b;
+                        self.flags.negative = *a < *b;
+                        self.flags.equal = *a == *b;
+                        self.flags.not_equal = *a != *b;
+                        self.flags.greater_than = *a > *b;
+                        self.flags.less_than = *a < *b;
+                        self.flags.greater_than_or_equal = *a >= *b;
+                        self.flags.less_than_or_equal = *a <= *b;
+                    }
+                    (ConstType::String(a), ConstType::String(b)) => {
+                        self.flags.zero = *a == *b;
+                        self.flags.negative = *a < *b;
+                        self.flags.equal = *a == *b;
+                        self.flags.not_equal = *a != *b;
+                        self.flags.greater_than = *a > *b;
+                        self.flags.less_than = *a < *b;
+                        self.flags.greater_than_or_equal = *a >= *b;
+                        self.flags.less_than_or_equal = *a <= *b;
+                    }
+                    _ => {
+                        // panic!("Type mismatch for SCMP instruction");
+                        return Err(Error::new(
+                            "E0202",
+                            "Type mismatch for SCMP instruction",
+                            *pos,
+                        ));
+                    }
                 }
-                Instruction::Label(_, ref pos) => {}
-                Instruction::Random(ref pos) => {
-                    let mut rng = rand::thread_rng();
-                    let random_number = rng.gen_range(0.0..1.0);
-                    self.stack.push(ConstType::Float(random_number));
+
+                self.stack.push(b);
+                self.stack.push(a);
+            }
+            Instruction::Label(_, _) => {}
+            Instruction::Random(_) => {
+                let random_number = self.rng.gen_range(0.0..1.0);
+                self.stack.push(ConstType::Float(random_number));
+            }
+            Instruction::SeedRandom(ref pos) => {
+                if self.stack.is_empty() {
+                    return Err(Error::new("E0201", "Cannot seed from an empty stack", *pos));
                 }
-                _ => {
-                    // panic!("Unimplemented instruction: {:?}", self.program[self.ip]);
-                    if let Instruction::Label(_, _) = self.program[self.ip] {
-                    } else {
-                        Error::new("Unimplemented instruction", "".to_string()).print();
+
+                let value = self.stack.pop().unwrap();
+                match value {
+                    ConstType::Integer(i) => {
+                        self.rng = StdRng::seed_from_u64(i as u64);
+                    }
+                    _ => {
+                        return Err(Error::new(
+                            "E0202",
+                            "Type mismatch for SRAND instruction",
+                            *pos,
+                        ));
                     }
                 }
             }
+            Instruction::RandomInt(lo, hi, ref pos) => {
+                if lo > hi {
+                    return Err(Error::new(
+                        "E0206",
+                        "Empty range for RANDINT instruction",
+                        *pos,
+                    ));
+                }
 
-            self.ip += 1;
+                let random_number = self.rng.gen_range(lo..=hi);
+                self.stack.push(ConstType::Integer(random_number));
+            }
+            _ => {
+                // panic!("Unimplemented instruction: {:?}", self.program[self.ip]);
+                if let Instruction::Label(_, _) = self.program[self.ip] {
+                } else {
+                    return Err(Error::new(
+                        "E0208",
+                        "Unimplemented instruction",
+                        self.program[self.ip].pos(),
+                    ));
+                }
+            }
         }
+
+        Ok(true)
     }
 
-    fn compile(&mut self) {
+    fn compile(&mut self) -> Result<(), Vec<Error>> {
         let mut arg_required = false;
         let mut arg_required_by = String::new();
+        let mut arg_required_at = Span::default();
+        let mut randint_lo: Option<i32> = None;
+        let mut errors = Vec::new();
 
-        for symbol in &self.symbols {
-            let pos = format!(
-                "{}:{}",
-                symbol.line_number as i32, symbol.column_number as i32
-            );
+        let (declarations, declared) = constants::declarations(&self.symbols, &mut errors);
+        let constants = constants::constants(&self.symbols, &declarations, &mut errors);
+
+        let mut symbols = self.symbols.iter().zip(declared).peekable();
+        while let Some((symbol, declared)) = symbols.next() {
+            if errors.len() >= self.max_errors {
+                break;
+            }
+            if declared {
+                continue;
+            }
+
+            let pos = symbol.span;
             // println!("{}, {}", symbol.line_number, symbol.column_number);
             match symbol.symbol_type {
                 SymbolType::Instruction => {
+                    let takes_variable = matches!(arg_required_by.as_str(), "set" | "get");
+                    if arg_required && takes_variable && !MNEMONICS.contains(&symbol.value.as_str())
+                    {
+                        let variable = Variable::new(&symbol.value);
+                        self.program.push(match arg_required_by.as_str() {
+                            "set" => Instruction::Set(variable, pos),
+                            _ => Instruction::Get(variable, pos),
+                        });
+                        arg_required = false;
+                        continue;
+                    }
+
+                    let takes_constant = matches!(arg_required_by.as_str(), "push" | "cmp");
+                    if arg_required && takes_constant && !MNEMONICS.contains(&symbol.value.as_str())
+                    {
+                        arg_required = false;
+                        match constants.get(&symbol.value) {
+                            Some(constant) if arg_required_by == "push" => {
+                                for value in &constant.values {
+                                    self.program.push(Instruction::Push(value.clone(), pos));
+                                }
+                                if constant.is_array() {
+                                    let count = ConstType::Integer(constant.values.len() as i32);
+                                    self.program.push(Instruction::Push(count, pos));
+                                }
+                            }
+                            Some(constant) if !constant.is_array() => {
+                                // Empty if its value was invalid, which is reported.
+                                if let Some(value) = constant.values.first() {
+                                    self.program.push(Instruction::Cmp(value.clone(), pos));
+                                }
+                            }
+                            Some(constant) => {
+                                let error = Error::new(
+                                    "E0117",
+                                    &format!("Cannot compare with an array: {}", symbol.value),
+                                    pos,
+                                )
+                                .with_note("array defined here", constant.span);
+                                errors.push(error);
+                            }
+                            None => {
+                                let mut error = Error::new(
+                                    "E0116",
+                                    &format!("Undefined constant: {}", symbol.value),
+                                    pos,
+                                );
+                                let names = constants.keys().map(String::as_str);
+                                if let Some(name) = suggest(&symbol.value, names) {
+                                    error = error.with_help(&format!("did you mean `{}`?", name));
+                                }
+                                errors.push(error);
+                            }
+                        }
+                        continue;
+                    }
+
                     if arg_required {
-                        // panic!("Missing argument for instruction: {}", arg_required_by);
-                        Error::new(
+                        errors.push(Error::new(
+                            "E0101",
                             &format!("Missing argument for instruction: {}", arg_required_by),
-                            pos.clone(),
-                        )
-                        .print();
-                        return;
+                            arg_required_at,
+                        ));
+                        arg_required = false;
+                        randint_lo = None;
                     }
 
+                    arg_required_at = pos;
                     match symbol.value.as_str() {
                         "push" => {
                             arg_required = true;
                             arg_required_by = String::from("push");
                         }
                         "pop" => {
-                            self.program.push(Instruction::Pop(pos.clone()));
+                            self.program.push(Instruction::Pop(pos));
+                        }
+                        "dup" => {
+                            self.program.push(Instruction::Duplicate(pos));
+                        }
+                        "swap" => {
+                            self.program.push(Instruction::Swap(pos));
+                        }
+                        "set" => {
+                            arg_required = true;
+                            arg_required_by = String::from("set");
+                        }
+                        "get" => {
+                            arg_required = true;
+                            arg_required_by = String::from("get");
                         }
                         "add" => {
-                            self.program.push(Instruction::Add(pos.clone()));
+                            self.program.push(Instruction::Add(pos));
                         }
                         "sub" => {
-                            self.program.push(Instruction::Sub(pos.clone()));
+                            self.program.push(Instruction::Sub(pos));
                         }
                         "mul" => {
-                            self.program.push(Instruction::Mul(pos.clone()));
+                            self.program.push(Instruction::Mul(pos));
                         }
                         "div" => {
-                            self.program.push(Instruction::Div(pos.clone()));
+                            self.program.push(Instruction::Div(pos));
                         }
                         "idiv" => {
-                            self.program.push(Instruction::IDiv(pos.clone()));
+                            self.program.push(Instruction::IDiv(pos));
                         }
                         "mod" => {
-                            self.program.push(Instruction::Mod(pos.clone()));
+                            self.program.push(Instruction::Mod(pos));
                         }
                         "print" => {
-                            self.program.push(Instruction::Print(pos.clone()));
+                            self.program.push(Instruction::Print(pos));
                         }
                         "read" => {
-                            self.program.push(Instruction::Read(pos.clone()));
+                            self.program.push(Instruction::Read(pos));
                         }
                         "atoi" => {
-                            self.program.push(Instruction::AToI(pos.clone()));
+                            self.program.push(Instruction::AToI(pos));
+                        }
+                        "ftoi" => {
+                            self.program.push(Instruction::FToI(pos));
                         }
                         "jmp" => {
                             arg_required = true;
@@ -809,229 +2272,343 @@ impl EvilStackVM {
                             arg_required = true;
                             arg_required_by = String::from("jneg");
                         }
+                        "call" => {
+                            arg_required = true;
+                            arg_required_by = String::from("call");
+                        }
                         "cmp" => {
                             arg_required = true;
                             arg_required_by = String::from("cmp");
                         }
                         "scmp" => {
-                            self.program.push(Instruction::CmpInStack(pos.clone()));
+                            self.program.push(Instruction::CmpInStack(pos));
                         }
                         "exit" => {
-                            self.program.push(Instruction::Exit(pos.clone()));
+                            self.program.push(Instruction::Exit(pos));
+                        }
+                        "assert" => {
+                            self.program.push(Instruction::Assert(pos));
+                        }
+                        "assert_eq" => {
+                            arg_required = true;
+                            arg_required_by = String::from("assert_eq");
                         }
                         "ret" => {
-                            self.program.push(Instruction::Return(pos.clone()));
+                            self.program.push(Instruction::Return(pos));
+                        }
+                        "try" => {
+                            arg_required = true;
+                            arg_required_by = String::from("try");
+                        }
+                        "endtry" => {
+                            self.program.push(Instruction::EndTry(pos));
+                        }
+                        "throw" => {
+                            self.program.push(Instruction::Throw(pos));
                         }
                         "rand" => {
-                            self.program.push(Instruction::Random(pos.clone()));
+                            self.program.push(Instruction::Random(pos));
+                        }
+                        "srand" => {
+                            self.program.push(Instruction::SeedRandom(pos));
+                        }
+                        "randint" => {
+                            arg_required = true;
+                            arg_required_by = String::from("randint");
                         }
                         "time" => {
-                            self.program.push(Instruction::Time(pos.clone()));
+                            self.program.push(Instruction::Time(pos));
                         }
                         _ => {
                             // panic!("Unknown instruction: {}", symbol.value);
-                            Error::new(
+                            let mut error = Error::new(
+                                "E0100",
                                 &format!("Unknown instruction: {}", symbol.value),
-                                pos.clone(),
-                            )
-                            .print();
-                            return;
+                                pos,
+                            );
+                            if let Some(name) = suggest(&symbol.value, MNEMONICS.iter().copied()) {
+                                error = error.with_help(&format!("did you mean `{}`?", name));
+                            }
+                            errors.push(error);
                         }
                     }
                 }
                 SymbolType::String => {
                     if !arg_required {
                         // panic!("Unexpected string literal: {}", symbol.value);
-                        Error::new(
+                        errors.push(Error::new(
+                            "E0102",
                             &format!("Unexpected string literal: {}", symbol.value),
-                            pos.clone(),
-                        )
-                        .print();
-                        return;
+                            pos,
+                        ));
+                        continue;
                     }
 
                     match arg_required_by.as_str() {
                         "push" => {
                             self.program.push(Instruction::Push(
                                 ConstType::String(symbol.value.clone()),
-                                pos.clone(),
+                                pos,
                             ));
                             arg_required = false;
                         }
                         "cmp" => {
                             let value = symbol.value.clone();
                             self.program
-                                .push(Instruction::Cmp(ConstType::String(value), pos.clone()));
+                                .push(Instruction::Cmp(ConstType::String(value), pos));
+                            arg_required = false;
+                        }
+                        "assert_eq" => {
+                            self.program
+                                .push(Instruction::AssertEq(symbol.value.clone(), pos));
                             arg_required = false;
                         }
                         _ => {
                             // panic!("Unexpected string literal: {}", symbol.value);
-                            Error::new(
+                            errors.push(Error::new(
+                                "E0102",
                                 &format!("Unexpected string literal: {}", symbol.value),
-                                pos.clone(),
-                            )
-                            .print();
-                            return;
+                                pos,
+                            ));
+                            arg_required = false;
+                            randint_lo = None;
                         }
                     }
                 }
                 SymbolType::Integer => {
                     if !arg_required {
                         // panic!("Unexpected integer literal: {}", symbol.value);
-                        Error::new(
+                        errors.push(Error::new(
+                            "E0102",
                             &format!("Unexpected integer literal: {}", symbol.value),
-                            pos.clone(),
-                        )
-                        .print();
-                        return;
+                            pos,
+                        ));
+                        continue;
                     }
 
+                    let value = match symbol.value.parse::<i32>() {
+                        Ok(value) => value,
+                        Err(_) => {
+                            errors.push(Error::new(
+                                "E0103",
+                                &format!("Invalid integer literal: {}", symbol.value),
+                                pos,
+                            ));
+                            arg_required = false;
+                            randint_lo = None;
+                            continue;
+                        }
+                    };
+
                     match arg_required_by.as_str() {
                         "push" => {
-                            let value = symbol.value.parse::<i32>().unwrap();
                             self.program
-                                .push(Instruction::Push(ConstType::Integer(value), pos.clone()));
+                                .push(Instruction::Push(ConstType::Integer(value), pos));
                             arg_required = false;
                         }
                         "cmp" => {
-                            let value = symbol.value.parse::<i32>().unwrap();
                             self.program
-                                .push(Instruction::Cmp(ConstType::Integer(value), pos.clone()));
+                                .push(Instruction::Cmp(ConstType::Integer(value), pos));
                             arg_required = false;
                         }
+                        "randint" => match randint_lo.take() {
+                            None => randint_lo = Some(value),
+                            Some(lo) => {
+                                self.program.push(Instruction::RandomInt(lo, value, pos));
+                                arg_required = false;
+                            }
+                        },
                         _ => {
                             // panic!("Unexpected integer literal: {}", symbol.value);
-                            Error::new(
+                            errors.push(Error::new(
+                                "E0102",
                                 &format!("Unexpected integer literal: {}", symbol.value),
-                                pos.clone(),
-                            )
-                            .print();
-                            return;
+                                pos,
+                            ));
+                            arg_required = false;
+                            randint_lo = None;
                         }
                     }
                 }
                 SymbolType::Float => {
                     if !arg_required {
                         // panic!("Unexpected float literal: {}", symbol.value);
-                        Error::new(
+                        errors.push(Error::new(
+                            "E0102",
                             &format!("Unexpected float literal: {}", symbol.value),
-                            pos.clone(),
-                        )
-                        .print();
-                        return;
+                            pos,
+                        ));
+                        continue;
                     }
 
+                    let value = match symbol.value.parse::<f32>() {
+                        Ok(value) => value,
+                        Err(_) => {
+                            errors.push(Error::new(
+                                "E0103",
+                                &format!("Invalid float literal: {}", symbol.value),
+                                pos,
+                            ));
+                            arg_required = false;
+                            randint_lo = None;
+                            continue;
+                        }
+                    };
+
                     match arg_required_by.as_str() {
                         "push" => {
-                            let value = symbol.value.parse::<f32>().unwrap();
                             self.program
-                                .push(Instruction::Push(ConstType::Float(value), pos.clone()));
+                                .push(Instruction::Push(ConstType::Float(value), pos));
                             arg_required = false;
                         }
                         "cmp" => {
-                            let value = symbol.value.parse::<f32>().unwrap();
                             self.program
-                                .push(Instruction::Cmp(ConstType::Float(value), pos.clone()));
+                                .push(Instruction::Cmp(ConstType::Float(value), pos));
                             arg_required = false;
                         }
                         _ => {
                             // panic!("Unexpected float literal: {}", symbol.value);
-                            Error::new(
+                            errors.push(Error::new(
+                                "E0102",
                                 &format!("Unexpected float literal: {}", symbol.value),
-                                pos.clone(),
-                            )
-                            .print();
-                            return;
+                                pos,
+                            ));
+                            arg_required = false;
+                            randint_lo = None;
                         }
                     }
                 }
+                SymbolType::Punctuation => {
+                    errors.push(Error::new(
+                        "E0102",
+                        &format!("Unexpected `{}`", symbol.value),
+                        pos,
+                    ));
+                }
                 SymbolType::Label => {
                     if arg_required {
-                        // panic!("Missing argument for instruction: {}", arg_required_by);
-                        Error::new(
+                        errors.push(Error::new(
+                            "E0101",
                             &format!("Missing argument for instruction: {}", arg_required_by),
-                            pos.clone(),
-                        )
-                        .print();
-                        return;
+                            arg_required_at,
+                        ));
+                        arg_required = false;
+                        randint_lo = None;
                     }
 
                     let label = &symbol.value[..symbol.value.len() - 1];
                     self.program
-                        .push(Instruction::Label(label.to_string(), pos.clone()));
+                        .push(Instruction::Label(label.to_string(), pos));
                 }
                 SymbolType::LabelReference => {
                     if !arg_required {
                         // panic!("Unexpected label reference: {}", symbol.value);
-                        Error::new(
+                        errors.push(Error::new(
+                            "E0102",
                             &format!("Unexpected label reference: {}", symbol.value),
-                            pos.clone(),
-                        )
-                        .print();
-                        return;
+                            pos,
+                        ));
+                        continue;
                     }
 
                     let label = &symbol.value[1..];
 
                     match arg_required_by.as_str() {
                         "jmp" => {
-                            self.program
-                                .push(Instruction::Jump(label.to_string(), pos.clone()));
+                            self.program.push(Instruction::Jump(label.to_string(), pos));
+                            arg_required = false;
+                        }
+                        "try" => {
+                            self.program.push(Instruction::Try(label.to_string(), pos));
                             arg_required = false;
                         }
                         "jeq" => {
                             self.program
-                                .push(Instruction::JumpEq(label.to_string(), pos.clone()));
+                                .push(Instruction::JumpEq(label.to_string(), pos));
                             arg_required = false;
                         }
                         "jne" => {
                             self.program
-                                .push(Instruction::JumpNotEq(label.to_string(), pos.clone()));
+                                .push(Instruction::JumpNotEq(label.to_string(), pos));
                             arg_required = false;
                         }
                         "jgt" => {
                             self.program
-                                .push(Instruction::JumpGt(label.to_string(), pos.clone()));
+                                .push(Instruction::JumpGt(label.to_string(), pos));
                             arg_required = false;
                         }
                         "jlt" => {
                             self.program
-                                .push(Instruction::JumpLt(label.to_string(), pos.clone()));
+                                .push(Instruction::JumpLt(label.to_string(), pos));
                             arg_required = false;
                         }
                         "jge" => {
                             self.program
-                                .push(Instruction::JumpGtEq(label.to_string(), pos.clone()));
+                                .push(Instruction::JumpGtEq(label.to_string(), pos));
                             arg_required = false;
                         }
                         "jle" => {
                             self.program
-                                .push(Instruction::JumpLtEq(label.to_string(), pos.clone()));
+                                .push(Instruction::JumpLtEq(label.to_string(), pos));
                             arg_required = false;
                         }
                         "jz" => {
                             self.program
-                                .push(Instruction::JumpZero(label.to_string(), pos.clone()));
+                                .push(Instruction::JumpZero(label.to_string(), pos));
                             arg_required = false;
                         }
                         "jnz" => {
                             self.program
-                                .push(Instruction::JumpNotZero(label.to_string(), pos.clone()));
+                                .push(Instruction::JumpNotZero(label.to_string(), pos));
                             arg_required = false;
                         }
                         "jneg" => {
                             self.program
-                                .push(Instruction::JumpNeg(label.to_string(), pos.clone()));
+                                .push(Instruction::JumpNeg(label.to_string(), pos));
+                            arg_required = false;
+                        }
+                        "call" => {
+                            // The argument and result counts follow the
+                            // label on the same line, and default to 0.
+                            let mut counts = Vec::new();
+                            while counts.len() < 2 {
+                                let Some((count, _)) = symbols.next_if(|(next, _)| {
+                                    matches!(next.symbol_type, SymbolType::Integer)
+                                        && next.span.same_line(pos)
+                                }) else {
+                                    break;
+                                };
+                                match count.value.parse::<usize>() {
+                                    Ok(count) => counts.push(count),
+                                    Err(_) => {
+                                        errors.push(Error::new(
+                                            "E0103",
+                                            &format!("Invalid count for call: {}", count.value),
+                                            count.span,
+                                        ));
+                                        counts.push(0);
+                                    }
+                                }
+                            }
+                            let args = counts.first().copied().unwrap_or(0);
+                            let results = counts.get(1).copied().unwrap_or(0);
+                            self.program.push(Instruction::Call(
+                                label.to_string(),
+                                args,
+                                results,
+                                pos,
+                            ));
                             arg_required = false;
                         }
                         _ => {
                             // panic!("Unexpected label reference: {}", symbol.value);
-                            Error::new(
+                            errors.push(Error::new(
+                                "E0102",
                                 &format!("Unexpected label reference: {}", symbol.value),
-                                pos.clone(),
-                            )
-                            .print();
-                            return;
+                                pos,
+                            ));
+                            arg_required = false;
+                            randint_lo = None;
                         }
                     }
                 }
@@ -1039,19 +2616,144 @@ impl EvilStackVM {
         }
 
         if arg_required {
-            // panic!("Missing argument for instruction: {}", arg_required_by);
-            Error::new(
+            errors.push(Error::new(
+                "E0101",
                 &format!("Missing argument for instruction: {}", arg_required_by),
-                "".to_string(),
-            )
-            .print();
-            return;
+                arg_required_at,
+            ));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Adds a stack trace to a runtime error: a note for every active call,
+    /// innermost first, and the top of the stack if a dump was asked for.
+    fn trace(&self, error: Error) -> Error {
+        let mut error = error;
+
+        // Recursive calls from the same place are shown once, with a count.
+        let mut calls: Vec<(&Frame, usize)> = Vec::new();
+        for frame in self.call_stack.iter().rev() {
+            match calls.last_mut() {
+                Some((last, count))
+                    if last.callee == frame.callee && last.call_site == frame.call_site =>
+                {
+                    *count += 1;
+                }
+                _ => calls.push((frame, 1)),
+            }
+        }
+
+        for (frame, count) in calls.iter().take(MAX_TRACE) {
+            let callee = self.callee(frame);
+            // Calls made by `execute_label` start at the label itself.
+            let mut message = if frame.call_site == self.program[frame.callee].pos() {
+                format!("in `{}`", callee)
+            } else {
+                format!("in `{}`, called here", callee)
+            };
+            if *count > 1 {
+                message += &format!(" ({} times)", count);
+            }
+            error = error.with_note(&message, frame.call_site);
+        }
+        if calls.len() > MAX_TRACE {
+            let hidden: usize = calls[MAX_TRACE..].iter().map(|(_, count)| count).sum();
+            error = error.with_help(&format!("{} more calls are not shown", hidden));
+        }
+
+        if self.stack_dump > 0 {
+            // The stack as the failing instruction found it.
+            let stack: Vec<&ConstType> = match &self.before {
+                Some((depth, values)) => self.stack.iter().take(*depth).chain(values).collect(),
+                None => self.stack.iter().collect(),
+            };
+            let values: Vec<String> = stack
+                .iter()
+                .rev()
+                .take(self.stack_dump)
+                .map(|value| value.to_string())
+                .collect();
+            let help = match (values.is_empty(), stack.len() > self.stack_dump) {
+                (true, _) => "the stack is empty".to_string(),
+                (false, false) => format!("stack, top first: {}", values.join(", ")),
+                (false, true) => format!(
+                    "stack, top first: {}, ... ({} values)",
+                    values.join(", "),
+                    stack.len()
+                ),
+            };
+            error = error.with_help(&help);
+        }
+
+        error
+    }
+
+    /// The error for a `ret` from a `call` that does not leave exactly the
+    /// declared number of results above the caller's stack.
+    fn wrong_results(&self, frame: &Frame, args: usize, results: usize, pos: Span) -> Error {
+        let callee = self.callee(frame);
+        let message = match self.stack.len().checked_sub(frame.base) {
+            Some(left) => format!(
+                "`{}` returned {} value{} but `call` declared {} result{}",
+                callee,
+                left,
+                if left == 1 { "" } else { "s" },
+                results,
+                if results == 1 { "" } else { "s" }
+            ),
+            None => format!(
+                "`{}` removed {} more value{} than its {} argument{}",
+                callee,
+                frame.base - self.stack.len(),
+                if frame.base - self.stack.len() == 1 {
+                    ""
+                } else {
+                    "s"
+                },
+                args,
+                if args == 1 { "" } else { "s" }
+            ),
+        };
+        Error::new("E0210", &message, pos)
+    }
+
+    fn unknown_label(&self, label: &str, pos: Span) -> Error {
+        let error = Error::new("E0200", &format!("Unknown label: {}", label), pos);
+        self.suggest_label(error, label)
+    }
+
+    fn suggest_label(&self, error: Error, label: &str) -> Error {
+        match suggest(label, self.labels.keys().map(String::as_str)) {
+            Some(name) => error.with_help(&format!("did you mean `@{}`?", name)),
+            None => error,
         }
     }
 
+    fn undefined_labels(&self) -> Vec<Error> {
+        self.program
+            .iter()
+            .filter_map(|instruction| match instruction.target() {
+                Some(label) if !self.labels.contains_key(label) => {
+                    let error = Error::new(
+                        "E0104",
+                        &format!("Undefined label: {}", label),
+                        instruction.pos(),
+                    );
+                    Some(self.suggest_label(error, label))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
     fn analyze_labels(&mut self) {
         for (i, instruction) in self.program.iter().enumerate() {
-            if let Instruction::Label(ref label, pos) = instruction {
+            if let Instruction::Label(ref label, _) = instruction {
                 self.labels.insert(label.clone(), i);
             }
         }
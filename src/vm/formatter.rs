@@ -0,0 +1,230 @@
+//! Canonical source formatting, used by `estack-vm fmt`.
+//!
+//! Labels are flush left and the instructions after them are indented, each
+//! instruction goes on its own line with a single space before every operand,
+//! label blocks are separated by one blank line and trailing comments on
+//! consecutive lines are aligned. Macro definitions and `.data` sections are
+//! laid out like label blocks, with `endmacro` and `.text` flush left.
+//! Comments are kept as they are written.
+
+use crate::{
+    evilstack_vm::MNEMONICS,
+    tokenizer::{Token, TokenType},
+};
+
+/// Words that start a line, besides instruction mnemonics.
+const KEYWORDS: &[&str] = &["include", "macro", "endmacro", "const", ".data", ".text"];
+
+/// How far instructions after a label are indented.
+const INDENT: usize = 2;
+
+struct Line {
+    indent: usize,
+    code: String,
+    comment: Option<String>,
+    is_label: bool,
+    /// Starts a label block or a macro definition.
+    starts_block: bool,
+    blank_before: bool,
+    /// Source line of the last token on this line.
+    source_line: usize,
+}
+
+fn render(token: &Token) -> String {
+    match token.token_type {
+        TokenType::StringLiteral => format!("\"{}\"", token.value),
+        TokenType::Comment => format!(";{}", token.value.trim_end()),
+        _ => token.value.clone(),
+    }
+}
+
+fn is_label(token: &Token) -> bool {
+    token.token_type == TokenType::Identifier && token.value.ends_with(':')
+}
+
+fn is_mnemonic(token: &Token) -> bool {
+    token.token_type == TokenType::Identifier
+        && !token.value.ends_with(':')
+        && !token.value.starts_with('@')
+        && !token.value.starts_with('$')
+}
+
+/// Whether the token is an instruction or a keyword, rather than a name
+/// such as a constant, a macro or a macro parameter.
+fn is_known(token: &Token) -> bool {
+    MNEMONICS.contains(&token.value.as_str()) || KEYWORDS.contains(&token.value.as_str())
+}
+
+fn is_keyword(token: &Token, keyword: &str) -> bool {
+    token.token_type == TokenType::Identifier && token.value == keyword
+}
+
+/// Splits the tokens into output lines, keeping track of where the source
+/// had blank lines.
+fn lines(tokens: &[Token]) -> Vec<Line> {
+    let mut lines: Vec<Line> = Vec::new();
+    let mut in_block = false;
+    // Whether the code before the current macro definition was in a block.
+    let mut outer_block = false;
+    // Source line of the current `macro` header, whose name and parameters
+    // are operands.
+    let mut header_line = None;
+    let mut last_line = 0;
+
+    for token in tokens {
+        if token.token_type == TokenType::EOF {
+            break;
+        }
+
+        let line = token.span.line;
+        let blank_before = last_line != 0 && line > last_line + 1;
+        last_line = line;
+
+        if let Some(previous) = lines.last_mut() {
+            // Names on the line of an instruction are its operands, such as
+            // `push NAME`, as are the name and parameters of a macro.
+            let is_name = is_mnemonic(token)
+                && (header_line == Some(line)
+                    || (previous.source_line == line && !is_known(token)));
+            let is_operand =
+                (!is_label(token) && !is_mnemonic(token) && token.token_type != TokenType::Comment)
+                    || is_name;
+
+            // Operands belong to the instruction before them, and a comment
+            // on the same line as code trails it.
+            if is_operand
+                && !previous.is_label
+                && !previous.code.is_empty()
+                && previous.comment.is_none()
+            {
+                previous.code += " ";
+                previous.code += &render(token);
+                previous.source_line = line;
+                continue;
+            }
+            if token.token_type == TokenType::Comment
+                && previous.source_line == line
+                && !previous.code.is_empty()
+                && previous.comment.is_none()
+            {
+                previous.comment = Some(render(token));
+                continue;
+            }
+        }
+
+        let (code, comment) = match token.token_type {
+            TokenType::Comment => (String::new(), Some(render(token))),
+            _ => (render(token), None),
+        };
+        let is_macro = is_keyword(token, "macro");
+        let is_endmacro = is_keyword(token, "endmacro");
+        let is_data = is_keyword(token, ".data");
+        let is_text = is_keyword(token, ".text");
+        if is_macro {
+            outer_block = in_block;
+            header_line = Some(line);
+        }
+        if is_endmacro {
+            in_block = outer_block;
+        }
+        if is_text {
+            in_block = false;
+        }
+        let flush_left = is_label(token) || is_macro || is_endmacro || is_data || is_text;
+
+        lines.push(Line {
+            indent: if in_block && !flush_left { INDENT } else { 0 },
+            code,
+            comment,
+            is_label: is_label(token),
+            starts_block: is_label(token) || is_macro || is_data,
+            blank_before,
+            source_line: line,
+        });
+
+        if is_label(token) || is_macro || is_data {
+            in_block = true;
+        }
+    }
+
+    lines
+}
+
+/// Formats the token stream of a whole file. The tokens must come from a
+/// file that tokenized without errors, or parts of it may be lost.
+pub fn format(tokens: &[Token]) -> String {
+    let mut lines = lines(tokens);
+
+    // Comments on their own line are indented like the code after them.
+    let mut next_indent = 0;
+    for line in lines.iter_mut().rev() {
+        if line.code.is_empty() {
+            line.indent = next_indent;
+        } else {
+            next_indent = line.indent;
+        }
+    }
+
+    // Every label block and macro definition starts after a blank line,
+    // placed above the comments that lead into it.
+    for i in 1..lines.len() {
+        if !lines[i].starts_block {
+            continue;
+        }
+        let mut first = i;
+        while first > 0 && !lines[first].blank_before && lines[first - 1].code.is_empty() {
+            first -= 1;
+        }
+        if first > 0 {
+            lines[first].blank_before = true;
+        }
+    }
+
+    // Trailing comments on consecutive lines start in the same column.
+    let mut start = 0;
+    while start < lines.len() {
+        let mut end = start;
+        while end < lines.len()
+            && !lines[end].code.is_empty()
+            && lines[end].comment.is_some()
+            && (end == start || !lines[end].blank_before)
+        {
+            end += 1;
+        }
+
+        if end == start {
+            start += 1;
+            continue;
+        }
+
+        let column = lines[start..end]
+            .iter()
+            .map(|line| line.indent + line.code.chars().count())
+            .max()
+            .unwrap_or(0);
+        for line in &mut lines[start..end] {
+            let padding = column - line.indent - line.code.chars().count();
+            line.code += &" ".repeat(padding);
+        }
+        start = end;
+    }
+
+    let mut out = String::new();
+    for (i, line) in lines.iter().enumerate() {
+        if i > 0 && line.blank_before {
+            out.push('\n');
+        }
+
+        out += &" ".repeat(line.indent);
+        out += &line.code;
+        if let Some(comment) = &line.comment {
+            if !line.code.is_empty() {
+                out.push(' ');
+            }
+            out += comment;
+        }
+        out.push('\n');
+    }
+
+    out
+}
@@ -0,0 +1,173 @@
+use crate::unit;
+use colored::Colorize;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+/// Seed passed to every program run by the test runner, so `rand` and
+/// `randint` produce the same values on every run.
+pub const TEST_SEED: u64 = 0;
+
+pub struct Outcome {
+    pub exit_code: i32,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl Outcome {
+    /// Renders the outcome in the format stored in `.expected` files.
+    pub fn render(&self) -> String {
+        format!(
+            "--- exit code\n{}\n--- stdout\n{}--- stderr\n{}",
+            self.exit_code, self.stdout, self.stderr
+        )
+    }
+}
+
+/// Collects every `.estk` file in `paths`, descending into directories.
+pub fn collect_programs(paths: &[PathBuf]) -> Vec<PathBuf> {
+    let mut programs = Vec::new();
+
+    for path in paths {
+        if path.is_dir() {
+            let mut entries: Vec<PathBuf> = match fs::read_dir(path) {
+                Ok(entries) => entries.filter_map(|e| e.ok()).map(|e| e.path()).collect(),
+                Err(_) => continue,
+            };
+            entries.sort();
+            programs.extend(collect_programs(&entries));
+        } else if path.extension().is_some_and(|ext| ext == "estk") {
+            programs.push(path.clone());
+        }
+    }
+
+    programs
+}
+
+/// Runs `program` in a child `estack-vm` process, feeding it the sibling
+/// `.stdin` file if there is one. A sibling `.args` file holds extra
+/// whitespace-separated command line arguments; if the first one is not a
+/// flag, it is the subcommand to run instead of running the program, and
+/// the program is not seeded.
+pub fn run_program(program: &Path) -> Result<Outcome, String> {
+    let exe = env::current_exe().map_err(|e| e.to_string())?;
+
+    let args = fs::read_to_string(program.with_extension("args")).unwrap_or_default();
+    let mut args: Vec<&str> = args.split_whitespace().collect();
+    let subcommand = match args.first() {
+        Some(arg) if !arg.starts_with('-') => Some(args.remove(0)),
+        _ => None,
+    };
+
+    let stdin = match fs::File::open(program.with_extension("stdin")) {
+        Ok(file) => Stdio::from(file),
+        Err(_) => Stdio::null(),
+    };
+
+    let seed = match subcommand {
+        Some(_) => None,
+        None => Some(TEST_SEED.to_string()),
+    };
+
+    let output = Command::new(exe)
+        .args(subcommand)
+        .args(seed.iter().flat_map(|seed| ["--seed", seed]))
+        .args(args)
+        .arg(program)
+        .stdin(stdin)
+        .env("NO_COLOR", "1")
+        .output()
+        .map_err(|e| e.to_string())?;
+
+    Ok(Outcome {
+        exit_code: output.status.code().unwrap_or(-1),
+        stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+        stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+    })
+}
+
+/// Runs every program found in `paths` and compares its output with the
+/// sibling `.expected` file. With `bless`, the `.expected` files are
+/// rewritten instead. Programs that define `test_` labels and have no
+/// `.args` file are run as unit tests instead, see [`unit::run`]. Returns
+/// `true` if every test passed.
+pub fn run(paths: &[PathBuf], bless: bool) -> bool {
+    let programs = collect_programs(paths);
+    let mut passed = 0;
+    let mut failed = 0;
+
+    for program in &programs {
+        let code = fs::read_to_string(program).unwrap_or_default();
+        if !program.with_extension("args").exists() && !unit::test_labels(program, &code).is_empty()
+        {
+            let (unit_passed, unit_failed) = unit::run(program);
+            passed += unit_passed;
+            failed += unit_failed;
+            continue;
+        }
+
+        let expected_path = program.with_extension("expected");
+        print!("test {} ... ", program.display());
+
+        let outcome = match run_program(program) {
+            Ok(outcome) => outcome,
+            Err(e) => {
+                println!("{}", "FAILED".red());
+                println!("    could not run program: {}", e);
+                failed += 1;
+                continue;
+            }
+        };
+        let actual = outcome.render();
+
+        if bless {
+            match fs::write(&expected_path, &actual) {
+                Ok(_) => {
+                    println!("{}", "blessed".yellow());
+                    passed += 1;
+                }
+                Err(e) => {
+                    println!("{}", "FAILED".red());
+                    println!("    could not write {}: {}", expected_path.display(), e);
+                    failed += 1;
+                }
+            }
+            continue;
+        }
+
+        match fs::read_to_string(&expected_path) {
+            Ok(expected) if expected == actual => {
+                println!("{}", "ok".green());
+                passed += 1;
+            }
+            Ok(expected) => {
+                println!("{}", "FAILED".red());
+                println!("---- expected ----\n{}", expected);
+                println!("---- actual ----\n{}", actual);
+                failed += 1;
+            }
+            Err(_) => {
+                println!("{}", "FAILED".red());
+                println!(
+                    "    missing {} (run with --bless to create it)",
+                    expected_path.display()
+                );
+                failed += 1;
+            }
+        }
+    }
+
+    let result = if failed == 0 {
+        "ok".green()
+    } else {
+        "FAILED".red()
+    };
+    println!(
+        "\ntest result: {}. {} passed; {} failed",
+        result, passed, failed
+    );
+
+    failed == 0
+}
@@ -0,0 +1,68 @@
+//! Syntax highlighting from semantic tokens, used by `estack-vm highlight`.
+
+use crate::tokenizer::{SemanticToken, SemanticTokenType};
+use colored::{ColoredString, Colorize};
+
+fn ansi_style(token_type: SemanticTokenType, text: &str) -> ColoredString {
+    match token_type {
+        SemanticTokenType::Instruction => text.blue().bold(),
+        SemanticTokenType::LabelDefinition => text.yellow().bold(),
+        SemanticTokenType::LabelReference => text.yellow(),
+        SemanticTokenType::Number => text.magenta(),
+        SemanticTokenType::String => text.green(),
+        SemanticTokenType::Comment => text.bright_black(),
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Splits `code` into the text between tokens and the tokens themselves,
+/// passing each piece to `render` with its token type, if any.
+fn render(
+    code: &str,
+    tokens: &[SemanticToken],
+    mut render: impl FnMut(Option<SemanticTokenType>, &str) -> String,
+) -> String {
+    let mut out = String::new();
+    let mut end = 0;
+
+    for token in tokens {
+        if token.range.start < end {
+            continue;
+        }
+        out += &render(None, &code[end..token.range.start]);
+        out += &render(Some(token.token_type), &code[token.range.clone()]);
+        end = token.range.end;
+    }
+    out += &render(None, &code[end..]);
+
+    out
+}
+
+/// Renders `code` with ANSI colors.
+pub fn ansi(code: &str, tokens: &[SemanticToken]) -> String {
+    render(code, tokens, |token_type, text| match token_type {
+        Some(token_type) => ansi_style(token_type, text).to_string(),
+        None => text.to_string(),
+    })
+}
+
+/// Renders `code` as an HTML `<pre>` block. Every token is wrapped in a
+/// `<span>` with an `estack-<type>` class, for example `estack-instruction`.
+pub fn html(code: &str, tokens: &[SemanticToken]) -> String {
+    let body = render(code, tokens, |token_type, text| match token_type {
+        Some(token_type) => format!(
+            "<span class=\"estack-{}\">{}</span>",
+            token_type.name(),
+            escape_html(text)
+        ),
+        None => escape_html(text),
+    });
+
+    format!("<pre class=\"estack\"><code>{}</code></pre>\n", body)
+}
@@ -0,0 +1,101 @@
+//! The undo log behind reverse debugging in `estack-vm debug`.
+//!
+//! Before every step the VM notes what the instruction may change, and puts
+//! it back when the step is undone: the instruction pointer, the flags, the
+//! top of the stack, the calls and `try` blocks the step left, the previous
+//! value of a variable it set, the random number generator it drew from and
+//! the line it read, which the next `read` returns again. Output that was
+//! printed cannot be taken back.
+
+use crate::evilstack_vm::{ConstType, Flags, Frame, Handler, Variable};
+use rand::rngs::StdRng;
+use std::collections::VecDeque;
+
+/// Steps kept by `estack-vm debug`.
+pub const DEFAULT_CAPACITY: usize = 100_000;
+
+/// The most values a single instruction takes off the stack.
+pub(crate) const MAX_POPPED: usize = 2;
+
+/// What a step may have changed, as it was before the step.
+pub(crate) struct Change {
+    pub ip: usize,
+    pub flags: Flags,
+    /// Height of the stack the step did not reach below.
+    pub depth: usize,
+    /// The values above `depth`, bottom first.
+    pub values: Vec<ConstType>,
+    /// Number of calls the step left active.
+    pub calls: usize,
+    /// The calls above `calls`, outermost first.
+    pub frames: Vec<Frame>,
+    /// Number of `try` blocks the step left active.
+    pub try_blocks: usize,
+    pub handlers: Vec<Handler>,
+    /// A variable the step set and its previous value.
+    pub variable: Option<(Variable, Option<ConstType>)>,
+    pub rng: Option<StdRng>,
+    /// Whether the step read a line, which is on top of the stack after it.
+    pub read: bool,
+}
+
+impl Change {
+    /// Extends the change to an unwinding of the stack, the calls and the
+    /// `try` blocks down to the given heights, as a caught error does. The
+    /// parts below the heights noted before the step are still unchanged.
+    pub fn unwind(
+        &mut self,
+        stack: (&[ConstType], usize),
+        calls: (&[Frame], usize),
+        handlers: (&[Handler], usize),
+    ) {
+        lower(&mut self.depth, &mut self.values, stack.0, stack.1);
+        lower(&mut self.calls, &mut self.frames, calls.0, calls.1);
+        lower(
+            &mut self.try_blocks,
+            &mut self.handlers,
+            handlers.0,
+            handlers.1,
+        );
+    }
+}
+
+/// Lowers the height `kept` of a saved top of `current` to `to`.
+fn lower<T: Clone>(kept: &mut usize, saved: &mut Vec<T>, current: &[T], to: usize) {
+    if to < *kept {
+        let mut below = current[to..*kept].to_vec();
+        below.append(saved);
+        *saved = below;
+        *kept = to;
+    }
+}
+
+/// The changes of the latest steps, dropping the oldest past its capacity.
+pub(crate) struct History {
+    changes: VecDeque<Change>,
+    capacity: usize,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> History {
+        History {
+            changes: VecDeque::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    pub fn push(&mut self, change: Change) {
+        if self.changes.len() == self.capacity {
+            self.changes.pop_front();
+        }
+        self.changes.push_back(change);
+    }
+
+    pub fn pop(&mut self) -> Option<Change> {
+        self.changes.pop_back()
+    }
+
+    pub fn last_mut(&mut self) -> Option<&mut Change> {
+        self.changes.back_mut()
+    }
+}
@@ -0,0 +1,257 @@
+//! Resolves `include "path.estk"` directives.
+//!
+//! Included files are tokenized with their own index in the [`SourceMap`] so
+//! diagnostics point into the right file, and are placed after the main
+//! program, which ends with an implicit `exit` so it does not run into them.
+//! Their code before the first label is left out, except for constants.
+//! Every label of an included file is prefixed with the module name, the
+//! file name without its extension: `square:` in `math.estk` is called as
+//! `@math.square` from outside, and as `@square` from inside the module.
+//! Constants are prefixed the same way: `push math.PI`.
+
+use crate::{
+    constants,
+    error::{Error, SourceMap, Span},
+    macros,
+    tokenizer::{Symbol, SymbolType, Tokenizer},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+struct Loader {
+    sources: SourceMap,
+    /// Symbols of every included file, in the order they finished loading.
+    modules: Vec<Symbol>,
+    /// Files being loaded, from the main program to the innermost include.
+    active: Vec<(PathBuf, String)>,
+    loaded: HashSet<PathBuf>,
+    module_names: HashMap<String, String>,
+    errors: Vec<Error>,
+    max_errors: usize,
+    /// Whether reading included files is denied.
+    sandbox: bool,
+}
+
+impl Loader {
+    /// Tokenizes a file, loads the files it includes and returns its own
+    /// symbols without the `include` directives.
+    fn file(&mut self, name: &str, source: &str, module: Option<&str>) -> Vec<Symbol> {
+        let file = self.sources.add(name, source);
+
+        let mut tokenizer = Tokenizer::new()
+            .with_file(file)
+            .with_max_errors(self.max_errors.saturating_sub(self.errors.len()).max(1));
+        if let Err(errors) = tokenizer.tokenize(source) {
+            self.errors.extend(errors);
+        }
+
+        let dir = Path::new(name).parent().unwrap_or(Path::new(""));
+        let mut symbols = Vec::new();
+        let mut rest = tokenizer.into_symbols().into_iter().peekable();
+
+        while let Some(symbol) = rest.next() {
+            if !matches!(symbol.symbol_type, SymbolType::Instruction) || symbol.value != "include" {
+                symbols.push(symbol);
+                continue;
+            }
+
+            match rest.next_if(|path| matches!(path.symbol_type, SymbolType::String)) {
+                Some(path) => self.include(dir, &path),
+                None => self.errors.push(Error::new(
+                    "E0101",
+                    "Missing argument for instruction: include",
+                    symbol.span,
+                )),
+            }
+        }
+
+        let mut symbols = macros::expand(symbols, &mut self.sources, &mut self.errors);
+
+        if let Some(module) = module {
+            // Code before the first label only runs when the file is run on
+            // its own, but the constants declared there are kept.
+            let start = symbols
+                .iter()
+                .position(|symbol| matches!(symbol.symbol_type, SymbolType::Label))
+                .unwrap_or(symbols.len());
+            let (_, declared) = constants::declarations(&symbols, &mut Vec::new());
+            let mut keep = declared
+                .into_iter()
+                .enumerate()
+                .map(|(i, declared)| i >= start || declared);
+            symbols.retain(|_| keep.next().unwrap_or(true));
+            namespace(&mut symbols, module);
+        }
+
+        symbols
+    }
+
+    fn include(&mut self, dir: &Path, path: &Symbol) {
+        let name = dir.join(&path.value).display().to_string();
+        if self.sandbox {
+            let message = format!(
+                "Cannot read included file {}: file access is disabled",
+                name
+            );
+            self.errors.push(Error::new("E0107", &message, path.span));
+            return;
+        }
+
+        let (canonical, source) = match fs::canonicalize(&name)
+            .and_then(|canonical| fs::read_to_string(&canonical).map(|s| (canonical, s)))
+        {
+            Ok(file) => file,
+            Err(error) => {
+                let message = format!("Cannot read included file {}: {}", name, error);
+                self.errors.push(Error::new("E0107", &message, path.span));
+                return;
+            }
+        };
+
+        if let Some(start) = self.active.iter().position(|(file, _)| *file == canonical) {
+            let mut cycle: Vec<&str> = self.active[start..]
+                .iter()
+                .map(|(_, name)| name.as_str())
+                .collect();
+            cycle.push(&name);
+            let message = format!("Include cycle: {}", cycle.join(" -> "));
+            self.errors.push(Error::new("E0108", &message, path.span));
+            return;
+        }
+
+        // A file included from several places is only loaded once.
+        if !self.loaded.insert(canonical.clone()) {
+            return;
+        }
+
+        let module = Path::new(&name)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if let Some(other) = self.module_names.get(&module) {
+            let message = format!("Module name `{}` is already used by {}", module, other);
+            let error = Error::new("E0109", &message, path.span)
+                .with_help("rename one of the files, module names come from file names");
+            self.errors.push(error);
+            return;
+        }
+        self.module_names.insert(module.clone(), name.clone());
+
+        self.active.push((canonical, name.clone()));
+        let symbols = self.file(&name, &source, Some(&module));
+        self.active.pop();
+
+        self.modules.extend(symbols);
+    }
+}
+
+/// Prefixes the labels and constants defined in `symbols`, and the
+/// references to them, with the module name.
+fn namespace(symbols: &mut [Symbol], module: &str) {
+    let defined: HashSet<String> = symbols
+        .iter()
+        .filter(|symbol| matches!(symbol.symbol_type, SymbolType::Label))
+        .map(|symbol| symbol.value.trim_end_matches(':').to_string())
+        .collect();
+
+    // Malformed declarations are reported when the program is compiled.
+    let (declarations, _) = constants::declarations(symbols, &mut Vec::new());
+    let constants: HashSet<String> = declarations
+        .iter()
+        .map(|declaration| symbols[declaration.name].value.clone())
+        .collect();
+
+    for symbol in symbols {
+        match symbol.symbol_type {
+            SymbolType::Label => symbol.value = format!("{}.{}", module, symbol.value),
+            SymbolType::LabelReference if defined.contains(&symbol.value[1..]) => {
+                symbol.value = format!("@{}.{}", module, &symbol.value[1..]);
+            }
+            SymbolType::Instruction if constants.contains(&symbol.value) => {
+                symbol.value = format!("{}.{}", module, symbol.value);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Tokenizes the main program `source`, read from the file `name`, together
+/// with every file it includes. Returns the loaded files, the symbols of the
+/// whole program and the errors found, keeping the symbols that were
+/// recognized even if there are errors.
+pub fn load(name: &str, source: &str, max_errors: usize) -> (SourceMap, Vec<Symbol>, Vec<Error>) {
+    load_with(name, source, max_errors, false)
+}
+
+/// Like [`load`], but every `include` fails, as the program may not read
+/// files.
+pub fn load_sandboxed(
+    name: &str,
+    source: &str,
+    max_errors: usize,
+) -> (SourceMap, Vec<Symbol>, Vec<Error>) {
+    load_with(name, source, max_errors, true)
+}
+
+fn load_with(
+    name: &str,
+    source: &str,
+    max_errors: usize,
+    sandbox: bool,
+) -> (SourceMap, Vec<Symbol>, Vec<Error>) {
+    let mut loader = Loader {
+        sources: SourceMap::default(),
+        modules: Vec::new(),
+        active: Vec::new(),
+        loaded: HashSet::new(),
+        module_names: HashMap::new(),
+        errors: Vec::new(),
+        max_errors,
+        sandbox,
+    };
+
+    if let Ok(canonical) = fs::canonicalize(name) {
+        loader.active.push((canonical.clone(), name.to_string()));
+        loader.loaded.insert(canonical);
+    }
+
+    let mut symbols = loader.file(name, source, None);
+
+    if !loader.modules.is_empty() {
+        let (_, declared) = constants::declarations(&symbols, &mut Vec::new());
+        let last_code = symbols
+            .iter()
+            .zip(declared)
+            .rev()
+            .find(|(_, declared)| !declared)
+            .map(|(symbol, _)| symbol);
+        let ends_with_exit = last_code.is_some_and(|symbol| {
+            matches!(symbol.symbol_type, SymbolType::Instruction)
+                && (symbol.value == "exit" || symbol.value == "ret")
+        });
+        if !ends_with_exit {
+            // On a line of its own, so it does not look like part of the
+            // last instruction or data entry.
+            let span = symbols.last().map_or(Span::new(1, 1, 0), |symbol| Span {
+                line: symbol.span.line + 1,
+                column: 1,
+                length: 0,
+                expansion: 0,
+                ..symbol.span
+            });
+            symbols.push(Symbol::new(
+                SymbolType::Instruction,
+                "exit".to_string(),
+                span,
+            ));
+        }
+        symbols.extend(loader.modules);
+    }
+
+    let mut errors = loader.errors;
+    errors.truncate(max_errors);
+    (loader.sources, symbols, errors)
+}
@@ -0,0 +1,25 @@
+// Errors carry their source span, file and help notes for diagnostics, and
+// are only ever created on the failure path.
+#![allow(clippy::result_large_err)]
+
+pub mod constants;
+pub mod coverage;
+pub mod debugger;
+pub mod docs;
+pub mod error;
+pub mod evilstack_vm;
+pub mod formatter;
+pub mod golden;
+pub mod highlight;
+pub mod history;
+pub mod include;
+pub mod limits;
+pub mod lint;
+pub mod macros;
+pub mod profile;
+pub mod snapshot;
+pub mod tokenizer;
+pub mod trace;
+pub mod typecheck;
+pub mod unit;
+pub mod verifier;
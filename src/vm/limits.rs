@@ -0,0 +1,59 @@
+//! Resource limits for running untrusted programs.
+//!
+//! Every limit is off by default. The VM checks them after each instruction,
+//! and the instruction limit before it, and stops with `E0215` when one is
+//! exceeded; a `try` block cannot catch it. The heap is measured after
+//! every instruction that may grow it, from byte counts kept as values come
+//! and go.
+//!
+//! The time limit is approximate: the clock is read only every
+//! [`CHECK_INTERVAL`] instructions, as that is more expensive, and a `read`
+//! waiting for input is not interrupted, so a run can go on past its
+//! deadline until the next check.
+
+use std::time::Duration;
+
+/// How many instructions run between checks of the time limit.
+pub const CHECK_INTERVAL: u64 = 1024;
+
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    /// Instructions executed.
+    pub instructions: Option<u64>,
+    /// Values on the stack.
+    pub stack: Option<usize>,
+    /// Active subroutine calls.
+    pub calls: Option<usize>,
+    /// Bytes held by the values on the stack, in variables and in registers.
+    pub heap: Option<usize>,
+    /// Bytes in a single string.
+    pub string: Option<usize>,
+    /// Wall-clock time of the run.
+    pub time: Option<Duration>,
+}
+
+impl Limits {
+    /// Sets the limit described by `spec`, written as `name=value` with one
+    /// of the names `instructions`, `stack`, `calls`, `heap`, `string` and
+    /// `time` (in milliseconds).
+    pub fn set(&mut self, spec: &str) -> Result<(), String> {
+        let Some((name, value)) = spec.split_once('=') else {
+            return Err(format!("expected `name=value`, found `{}`", spec));
+        };
+        let value: u64 = value
+            .parse()
+            .map_err(|_| format!("invalid value for the {} limit: {}", name, value))?;
+
+        match name {
+            "instructions" => self.instructions = Some(value),
+            "stack" => self.stack = Some(value as usize),
+            "calls" => self.calls = Some(value as usize),
+            "heap" => self.heap = Some(value as usize),
+            "string" => self.string = Some(value as usize),
+            "time" => self.time = Some(Duration::from_millis(value)),
+            _ => return Err(format!("unknown limit: {}", name)),
+        }
+
+        Ok(())
+    }
+}
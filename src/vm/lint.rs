@@ -0,0 +1,303 @@
+//! Warnings for common mistakes, reported by `estack-vm lint`.
+//!
+//! Like the VM, the linter treats every jump as a call: the code after a
+//! jump only runs if the jump target can return. Each label's subroutine is
+//! summarized by whether it can return, and those summaries decide which
+//! code is reachable.
+
+use crate::{error::Error, evilstack_vm::Instruction, unit::TEST_PREFIX};
+use std::collections::{HashMap, HashSet};
+
+struct Linter<'a> {
+    program: &'a [Instruction],
+    labels: &'a HashMap<String, usize>,
+    /// Labels whose subroutine can reach a `ret`.
+    returns: HashSet<String>,
+    warnings: Vec<(usize, Error)>,
+}
+
+impl<'a> Linter<'a> {
+    fn warn(&mut self, ip: usize, code: &'static str, message: &str, help: Option<String>) {
+        let mut warning = Error::warning(code, message, self.program[ip].pos());
+        if let Some(help) = help {
+            warning = warning.with_help(&help);
+        }
+        self.warnings.push((ip, warning));
+    }
+
+    /// Returns the instructions that can run after the one at `ip` without
+    /// leaving the current subroutine.
+    fn successors(&self, ip: usize) -> Vec<usize> {
+        let instruction = &self.program[ip];
+        match instruction {
+            Instruction::Exit(_) | Instruction::Return(_) | Instruction::Throw(_) => Vec::new(),
+            // The handler of a `try` block is part of the same subroutine.
+            Instruction::Try(label, _) => {
+                let mut next = vec![ip + 1];
+                next.extend(self.labels.get(label));
+                next
+            }
+            Instruction::Jump(label, _) | Instruction::Call(label, _, _, _)
+                if !self.returns.contains(label) =>
+            {
+                Vec::new()
+            }
+            _ => vec![ip + 1],
+        }
+    }
+
+    /// Every instruction reachable from `start` without following jumps into
+    /// other subroutines.
+    fn body(&self, start: usize) -> HashSet<usize> {
+        let mut body = HashSet::new();
+        let mut work = vec![start];
+
+        while let Some(ip) = work.pop() {
+            if ip >= self.program.len() || !body.insert(ip) {
+                continue;
+            }
+            work.extend(self.successors(ip));
+        }
+
+        body
+    }
+
+    fn summarize(&mut self) {
+        loop {
+            let mut changed = false;
+            for (label, ip) in self.labels {
+                if self.returns.contains(label) {
+                    continue;
+                }
+                let returns = self
+                    .body(*ip)
+                    .iter()
+                    .any(|ip| matches!(self.program[*ip], Instruction::Return(_)));
+                if returns {
+                    self.returns.insert(label.clone());
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+    }
+
+    /// The label whose block contains `ip`, if any.
+    fn enclosing_label(&self, ip: usize) -> Option<&'a str> {
+        self.program[..=ip]
+            .iter()
+            .rev()
+            .find_map(|instruction| match instruction {
+                Instruction::Label(label, _) => Some(label.as_str()),
+                _ => None,
+            })
+    }
+
+    fn unreachable_code(&mut self, reachable: &HashSet<usize>) {
+        for ip in 1..self.program.len() {
+            if reachable.contains(&ip) || matches!(self.program[ip], Instruction::Label(_, _)) {
+                continue;
+            }
+            if !reachable.contains(&(ip - 1)) {
+                continue;
+            }
+
+            let help = match &self.program[ip - 1] {
+                Instruction::Exit(_) => "any code after `exit` never runs".to_string(),
+                Instruction::Return(_) => "any code after `ret` never runs".to_string(),
+                Instruction::Throw(_) => "any code after `throw` never runs".to_string(),
+                Instruction::Jump(label, _) => format!(
+                    "`{}` never returns, so execution never continues after `jmp @{}`",
+                    label, label
+                ),
+                Instruction::Call(label, _, _, _) => format!(
+                    "`{}` never returns, so execution never continues after `call @{}`",
+                    label, label
+                ),
+                _ => continue,
+            };
+            self.warn(ip, "W0301", "Unreachable code", Some(help));
+        }
+    }
+
+    fn unused_labels(&mut self) {
+        let used: HashSet<&str> = self
+            .program
+            .iter()
+            .filter_map(|instruction| instruction.target())
+            .collect();
+
+        for ip in 0..self.program.len() {
+            if let Instruction::Label(label, _) = &self.program[ip] {
+                // Tests in included files are prefixed with the module name.
+                let name = label.rsplit('.').next().unwrap_or(label);
+                if !used.contains(label.as_str()) && !name.starts_with(TEST_PREFIX) {
+                    let message = format!("Unused label: {}", label);
+                    self.warn(ip, "W0302", &message, None);
+                }
+            }
+        }
+    }
+
+    fn fall_through(&mut self, reachable: &HashSet<usize>) {
+        let program = self.program;
+        for ip in 0..program.len().saturating_sub(1) {
+            let Instruction::Label(next, _) = &program[ip + 1] else {
+                continue;
+            };
+            if matches!(program[ip], Instruction::Label(_, _))
+                || !reachable.contains(&ip)
+                || !self.successors(ip).contains(&(ip + 1))
+            {
+                continue;
+            }
+
+            // A label that jumps back to itself is the head of a loop.
+            let is_loop = self
+                .body(ip + 1)
+                .iter()
+                .any(|ip| program[*ip].target() == Some(next.as_str()));
+            if is_loop {
+                continue;
+            }
+
+            let message = match self.enclosing_label(ip) {
+                Some(label) => format!("`{}` falls through into `{}`", label, next),
+                None => format!("Top-level code falls through into `{}`", next),
+            };
+            let help = format!(
+                "add `exit`, `ret` or `jmp` before `{}:` if this is not intended",
+                next
+            );
+            self.warn(ip + 1, "W0303", &message, Some(help));
+        }
+    }
+
+    fn top_level_return(&mut self) {
+        let mut body: Vec<usize> = self.body(0).into_iter().collect();
+        body.sort();
+
+        for ip in body {
+            if matches!(self.program[ip], Instruction::Return(_)) {
+                self.warn(
+                    ip,
+                    "W0304",
+                    "`ret` is reachable from the top level",
+                    Some(
+                        "there is no caller to return to here, so the program fails with E0204"
+                            .to_string(),
+                    ),
+                );
+            }
+        }
+    }
+
+    fn jumps_into_subroutines(&mut self) {
+        let mut entries: Vec<(&String, &usize)> = self
+            .labels
+            .iter()
+            .filter(|(label, _)| self.returns.contains(*label))
+            .collect();
+        entries.sort_by_key(|(_, ip)| **ip);
+
+        let mut found = Vec::new();
+        for (subroutine, start) in entries {
+            let body = self.body(*start);
+            for (ip, instruction) in self.program.iter().enumerate() {
+                let Some(label) = instruction.target() else {
+                    continue;
+                };
+                match self.labels.get(label) {
+                    Some(target) if target != start && body.contains(target) => {}
+                    _ => continue,
+                }
+                if body.contains(&ip) {
+                    continue;
+                }
+
+                let message = format!("Jump into the middle of subroutine `{}`", subroutine);
+                let note = format!("`{}` is part of `{}`, which starts here", label, subroutine);
+                found.push((ip, message, note, self.program[*start].pos()));
+            }
+        }
+
+        for (ip, message, note, start) in found {
+            let warning =
+                Error::warning("W0305", &message, self.program[ip].pos()).with_note(&note, start);
+            self.warnings.push((ip, warning));
+        }
+    }
+
+    fn unused_comparisons(&mut self) {
+        for ip in 0..self.program.len() {
+            if !matches!(
+                self.program[ip],
+                Instruction::Cmp(_, _) | Instruction::CmpInStack(_)
+            ) {
+                continue;
+            }
+
+            // Skip instructions that neither change the flags nor leave the
+            // block, and look at the first one that does.
+            let used = self.program[ip + 1..]
+                .iter()
+                .find(|instruction| {
+                    instruction.target().is_some()
+                        || matches!(
+                            instruction,
+                            Instruction::Cmp(_, _)
+                                | Instruction::CmpInStack(_)
+                                | Instruction::Exit(_)
+                                | Instruction::Return(_)
+                        )
+                })
+                .is_some_and(|instruction| {
+                    instruction.target().is_some()
+                        && !matches!(
+                            instruction,
+                            Instruction::Jump(_, _)
+                                | Instruction::Call(_, _, _, _)
+                                | Instruction::Try(_, _)
+                        )
+                });
+
+            if !used {
+                self.warn(
+                    ip,
+                    "W0306",
+                    "Comparison is never used",
+                    Some("follow the comparison with a conditional jump such as `jeq`".to_string()),
+                );
+            }
+        }
+    }
+}
+
+/// Lints `program` and returns the warnings in program order.
+pub(crate) fn lint(program: &[Instruction], labels: &HashMap<String, usize>) -> Vec<Error> {
+    let mut linter = Linter {
+        program,
+        labels,
+        returns: HashSet::new(),
+        warnings: Vec::new(),
+    };
+    linter.summarize();
+
+    let mut reachable = HashSet::new();
+    for start in std::iter::once(0).chain(labels.values().copied()) {
+        reachable.extend(linter.body(start));
+    }
+
+    linter.unreachable_code(&reachable);
+    linter.unused_labels();
+    linter.fall_through(&reachable);
+    linter.top_level_return();
+    linter.jumps_into_subroutines();
+    linter.unused_comparisons();
+
+    let mut warnings = linter.warnings;
+    warnings.sort_by_key(|(ip, _)| *ip);
+    warnings.into_iter().map(|(_, warning)| warning).collect()
+}
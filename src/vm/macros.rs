@@ -0,0 +1,285 @@
+//! Expands `macro name params... ... endmacro` definitions.
+//!
+//! A macro is used like an instruction, with its arguments on the same line,
+//! and the use is replaced by a copy of the body in which every `$param` is
+//! replaced by the argument. Labels defined in the body are renamed for each
+//! use, so a macro with a loop can be used more than once. Macros are local
+//! to the file they are defined in, and can be used before their definition.
+//!
+//! Every copied symbol keeps the position it has in the definition, and is
+//! tagged with an expansion recorded in the [`SourceMap`] so diagnostics can
+//! also point at the use.
+
+use crate::{
+    docs,
+    error::{suggest, Error, SourceMap, Span},
+    tokenizer::{Symbol, SymbolType},
+};
+use std::collections::{HashMap, HashSet};
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Symbol>,
+    /// Position of the name in the definition.
+    span: Span,
+}
+
+fn is_instruction(symbol: &Symbol, mnemonic: &str) -> bool {
+    matches!(symbol.symbol_type, SymbolType::Instruction) && symbol.value == mnemonic
+}
+
+/// Labels defined in the macro body, without the trailing `:`.
+fn local_labels(body: &[Symbol]) -> HashSet<&str> {
+    body.iter()
+        .filter(|symbol| matches!(symbol.symbol_type, SymbolType::Label))
+        .map(|symbol| symbol.value.trim_end_matches(':'))
+        .collect()
+}
+
+struct Expander<'a> {
+    macros: HashMap<String, Macro>,
+    sources: &'a mut SourceMap,
+    errors: &'a mut Vec<Error>,
+}
+
+impl Expander<'_> {
+    /// Removes the macro definitions from `symbols` and collects them.
+    fn definitions(&mut self, symbols: Vec<Symbol>) -> Vec<Symbol> {
+        let mut rest = symbols.into_iter().peekable();
+        let mut program = Vec::new();
+
+        while let Some(symbol) = rest.next() {
+            if is_instruction(&symbol, "endmacro") {
+                self.errors.push(Error::new(
+                    "E0110",
+                    "`endmacro` without a matching `macro`",
+                    symbol.span,
+                ));
+                continue;
+            }
+            if !is_instruction(&symbol, "macro") {
+                program.push(symbol);
+                continue;
+            }
+
+            let keyword = symbol.span;
+            let name = rest.next_if(|name| {
+                matches!(name.symbol_type, SymbolType::Instruction) && name.span.same_line(keyword)
+            });
+
+            let mut params: Vec<Symbol> = Vec::new();
+            while let Some(param) = rest.next_if(|param| param.span.same_line(keyword)) {
+                params.push(param);
+            }
+
+            let mut body = Vec::new();
+            let mut terminated = false;
+            for symbol in rest.by_ref() {
+                if is_instruction(&symbol, "endmacro") {
+                    terminated = true;
+                    break;
+                }
+                if is_instruction(&symbol, "macro") {
+                    self.errors.push(Error::new(
+                        "E0110",
+                        "Macros cannot be defined inside a macro",
+                        symbol.span,
+                    ));
+                }
+                body.push(symbol);
+            }
+
+            if !terminated {
+                self.errors.push(
+                    Error::new("E0110", "Unterminated macro definition", keyword)
+                        .with_help("end the macro with `endmacro`"),
+                );
+            }
+
+            let Some(name) = name else {
+                self.errors
+                    .push(Error::new("E0110", "Missing macro name", keyword));
+                continue;
+            };
+            self.define(name, params, body);
+        }
+
+        program
+    }
+
+    fn define(&mut self, name: Symbol, params: Vec<Symbol>, body: Vec<Symbol>) {
+        if docs::instruction(&name.value).is_some() || name.value.starts_with('$') {
+            let message = format!("Invalid macro name: {}", name.value);
+            self.errors.push(Error::new("E0110", &message, name.span));
+            return;
+        }
+        if let Some(previous) = self.macros.get(&name.value) {
+            let message = format!("Macro `{}` is already defined", name.value);
+            let error = Error::new("E0110", &message, name.span)
+                .with_note("previously defined here", previous.span);
+            self.errors.push(error);
+            return;
+        }
+
+        let mut names = Vec::new();
+        for param in &params {
+            if !matches!(param.symbol_type, SymbolType::Instruction) || names.contains(&param.value)
+            {
+                let message = format!("Invalid macro parameter: {}", param.value);
+                self.errors.push(Error::new("E0110", &message, param.span));
+                continue;
+            }
+            names.push(param.value.clone());
+        }
+
+        for symbol in &body {
+            if let Some(param) = symbol.value.strip_prefix('$') {
+                if matches!(symbol.symbol_type, SymbolType::Instruction)
+                    && !names.iter().any(|name| name == param)
+                {
+                    let message = format!("Unknown macro parameter: {}", symbol.value);
+                    let mut error = Error::new("E0113", &message, symbol.span);
+                    if let Some(suggestion) = suggest(param, names.iter().map(String::as_str)) {
+                        error = error.with_help(&format!("did you mean `${}`?", suggestion));
+                    }
+                    self.errors.push(error);
+                }
+            }
+        }
+
+        self.macros.insert(
+            name.value,
+            Macro {
+                params: names,
+                body,
+                span: name.span,
+            },
+        );
+    }
+
+    /// Replaces every macro use in `symbols` with the macro body. `active`
+    /// holds the macros being expanded, outermost first.
+    fn expand(&mut self, symbols: Vec<Symbol>, active: &mut Vec<String>) -> Vec<Symbol> {
+        let mut rest = symbols.into_iter().peekable();
+        let mut program = Vec::new();
+
+        while let Some(symbol) = rest.next() {
+            if !matches!(symbol.symbol_type, SymbolType::Instruction)
+                || !self.macros.contains_key(&symbol.value)
+            {
+                program.push(symbol);
+                continue;
+            }
+
+            let mut args = Vec::new();
+            // Names that are not instructions, such as constants, can be
+            // passed as arguments too.
+            while let Some(arg) = rest.next_if(|arg| {
+                let is_code = match arg.symbol_type {
+                    SymbolType::Instruction => docs::instruction(&arg.value).is_some(),
+                    SymbolType::Label => true,
+                    _ => false,
+                };
+                !is_code && arg.span.same_line(symbol.span)
+            }) {
+                args.push(arg);
+            }
+
+            let definition = &self.macros[&symbol.value];
+            if args.len() != definition.params.len() {
+                let message = format!(
+                    "Macro `{}` takes {} argument{} but {} {} given",
+                    symbol.value,
+                    definition.params.len(),
+                    if definition.params.len() == 1 {
+                        ""
+                    } else {
+                        "s"
+                    },
+                    args.len(),
+                    if args.len() == 1 { "was" } else { "were" },
+                );
+                let error = Error::new("E0111", &message, symbol.span)
+                    .with_note("macro defined here", definition.span);
+                self.errors.push(error);
+                continue;
+            }
+
+            if active.contains(&symbol.value) {
+                let mut cycle = active.clone();
+                cycle.push(symbol.value.clone());
+                let message = format!("Recursive macro expansion: {}", cycle.join(" -> "));
+                self.errors.push(Error::new("E0112", &message, symbol.span));
+                continue;
+            }
+
+            let body = self.instantiate(&symbol, &args);
+            active.push(symbol.value.clone());
+            program.extend(self.expand(body, active));
+            active.pop();
+        }
+
+        program
+    }
+
+    /// Copies the body of the macro used by `call`, with the parameters
+    /// replaced by `args` and the local labels renamed.
+    fn instantiate(&mut self, call: &Symbol, args: &[Symbol]) -> Vec<Symbol> {
+        let expansion = self.sources.add_expansion(&call.value, call.span);
+        let definition = &self.macros[&call.value];
+        let labels = local_labels(&definition.body);
+        let rename = |label: &str| format!("{}#{}", label, expansion);
+
+        let mut body = Vec::new();
+        for symbol in &definition.body {
+            let mut symbol = symbol.clone();
+            symbol.span.expansion = expansion;
+
+            match symbol.symbol_type {
+                SymbolType::Instruction if symbol.value.starts_with('$') => {
+                    // Unknown parameters were reported with the definition.
+                    let param = &symbol.value[1..];
+                    if let Some(i) = definition.params.iter().position(|name| name == param) {
+                        // The argument takes the place of the parameter, so
+                        // it stays on the line of a macro it is passed on to.
+                        body.push(Symbol {
+                            span: symbol.span,
+                            ..args[i].clone()
+                        });
+                    }
+                    continue;
+                }
+                SymbolType::Label => {
+                    symbol.value = rename(symbol.value.trim_end_matches(':')) + ":";
+                }
+                SymbolType::LabelReference if labels.contains(&symbol.value[1..]) => {
+                    symbol.value = format!("@{}", rename(&symbol.value[1..]));
+                }
+                _ => {}
+            }
+            body.push(symbol);
+        }
+
+        body
+    }
+}
+
+/// Collects the macros defined in `symbols`, the symbols of a single file,
+/// and expands every use of them. Errors are added to `errors`.
+pub fn expand(
+    symbols: Vec<Symbol>,
+    sources: &mut SourceMap,
+    errors: &mut Vec<Error>,
+) -> Vec<Symbol> {
+    let mut expander = Expander {
+        macros: HashMap::new(),
+        sources,
+        errors,
+    };
+
+    let program = expander.definitions(symbols);
+    if expander.macros.is_empty() {
+        return program;
+    }
+    expander.expand(program, &mut Vec::new())
+}
@@ -1,20 +1,229 @@
-pub mod error;
-pub mod evilstack_vm;
-pub mod tokenizer;
-
+use evilstack_lang::{
+    coverage::Coverage,
+    debugger,
+    error::{self, Error, ErrorFormat, Severity, SourceMap, DEFAULT_MAX_ERRORS},
+    evilstack_vm::EvilStackVM,
+    formatter, golden, highlight, history, include,
+    limits::Limits,
+    profile::Profiler,
+    snapshot::Snapshot,
+    tokenizer::{self, Symbol},
+    trace::{self, TraceFormat, Tracer},
+    unit,
+};
 use std::env::{self, current_dir};
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::{self, Read, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+fn usage(program: &str) -> ! {
+    eprintln!(
+        "Usage: {} [--seed <number>] [--max-errors <number>] [--stack-dump <number>] [--limit <name>=<value>]... [--sandbox] [--error-format=human|json] <filename>",
+        program
+    );
+    eprintln!(
+        "       {} [--snapshot-on-exit <path>] (--resume <snapshot> | <filename>)",
+        program
+    );
+    eprintln!(
+        "       {} [--trace | --trace-file <path>] [--trace-format=text|binary] [--trace-label <label>] [--trace-every <number>] <filename>",
+        program
+    );
+    eprintln!(
+        "       {} check [--max-errors <number>] [--error-format=human|json] <filename>",
+        program
+    );
+    eprintln!(
+        "       {} lint [--max-errors <number>] [--error-format=human|json] <filename>",
+        program
+    );
+    eprintln!(
+        "       {} profile [--folded <path>] [--no-time] [--seed <number>] [--limit <name>=<value>]... <filename>",
+        program
+    );
+    eprintln!(
+        "       {} coverage [--lcov <path>] [--seed <number>] [--limit <name>=<value>]... <filename>",
+        program
+    );
+    eprintln!(
+        "       {} debug [--seed <number>] (--resume <snapshot> | <filename>)",
+        program
+    );
+    eprintln!("       {} fmt [--check | --write] <filenames...>", program);
+    eprintln!("       {} highlight [--html] <filename>", program);
+    eprintln!("       {} replay <trace file>", program);
+    eprintln!("       {} test [--bless] [paths...]", program);
+    std::process::exit(1);
+}
+
+struct Options {
+    seed: Option<u64>,
+    max_errors: usize,
+    /// How many stack values are shown with a runtime error.
+    stack_dump: usize,
+    limits: Limits,
+    /// Deny the program access to files and stdin.
+    sandbox: bool,
+    trace: Option<TraceOptions>,
+    /// Where the state of the run is written when it ends.
+    snapshot: Option<String>,
+    /// The snapshot to continue instead of running a program from the start.
+    resume: Option<String>,
+    /// Where `profile` writes the folded call stacks, `-` for stdout.
+    folded: Option<String>,
+    /// Leave the time column out of the `profile` report.
+    no_time: bool,
+    /// Where `coverage` writes the lcov tracefile, `-` for stdout.
+    lcov: Option<String>,
+    error_format: ErrorFormat,
+    filename: String,
+}
+
+struct TraceOptions {
+    /// Where the trace is written, stderr if `None`.
+    file: Option<String>,
+    format: TraceFormat,
+    label: Option<String>,
+    every: u64,
+}
+
+/// Parses the options shared by every command that takes a single program,
+/// starting at `args[first]`.
+fn parse_options(args: &[String], first: usize) -> Options {
+    let mut seed: Option<u64> = None;
+    let mut max_errors = DEFAULT_MAX_ERRORS;
+    let mut stack_dump = 0;
+    let mut limits = Limits::default();
+    let mut sandbox = false;
+    let mut trace = false;
+    let mut snapshot = None;
+    let mut resume = None;
+    let mut folded = None;
+    let mut no_time = false;
+    let mut lcov = None;
+    let mut trace_options = TraceOptions {
+        file: None,
+        format: TraceFormat::Text,
+        label: None,
+        every: 1,
+    };
+    let mut error_format = ErrorFormat::Human;
+    let mut filename: Option<&String> = None;
+
+    let mut rest = args.iter().skip(first);
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--seed" => match rest.next().map(|value| value.parse::<u64>()) {
+                Some(Ok(value)) => seed = Some(value),
+                _ => usage(&args[0]),
+            },
+            "--max-errors" => match rest.next().map(|value| value.parse::<usize>()) {
+                Some(Ok(value)) if value > 0 => max_errors = value,
+                _ => usage(&args[0]),
+            },
+            "--stack-dump" => match rest.next().map(|value| value.parse::<usize>()) {
+                Some(Ok(value)) => stack_dump = value,
+                _ => usage(&args[0]),
+            },
+            "--limit" => match rest.next().map(|spec| limits.set(spec)) {
+                Some(Ok(())) => {}
+                Some(Err(message)) => {
+                    eprintln!("error: {}", message);
+                    usage(&args[0]);
+                }
+                None => usage(&args[0]),
+            },
+            "--sandbox" => sandbox = true,
+            "--trace" => trace = true,
+            "--trace-file" => match rest.next() {
+                Some(file) => {
+                    trace = true;
+                    trace_options.file = Some(file.clone());
+                }
+                None => usage(&args[0]),
+            },
+            "--snapshot-on-exit" => match rest.next() {
+                Some(path) => snapshot = Some(path.clone()),
+                None => usage(&args[0]),
+            },
+            "--resume" => match rest.next() {
+                Some(path) => resume = Some(path.clone()),
+                None => usage(&args[0]),
+            },
+            "--folded" => match rest.next() {
+                Some(path) => folded = Some(path.clone()),
+                None => usage(&args[0]),
+            },
+            "--no-time" => no_time = true,
+            "--lcov" => match rest.next() {
+                Some(path) => lcov = Some(path.clone()),
+                None => usage(&args[0]),
+            },
+            "--trace-format=text" => trace_options.format = TraceFormat::Text,
+            "--trace-format=binary" => trace_options.format = TraceFormat::Binary,
+            "--trace-label" => match rest.next() {
+                Some(label) => trace_options.label = Some(label.clone()),
+                None => usage(&args[0]),
+            },
+            "--trace-every" => match rest.next().map(|value| value.parse::<u64>()) {
+                Some(Ok(value)) if value > 0 => trace_options.every = value,
+                _ => usage(&args[0]),
+            },
+            "--error-format=human" => error_format = ErrorFormat::Human,
+            "--error-format=json" => error_format = ErrorFormat::Json,
+            _ if filename.is_none() => filename = Some(arg),
+            _ => usage(&args[0]),
+        }
+    }
+
+    // A resumed run is named after its snapshot, which holds the program.
+    let filename = match (filename, &resume) {
+        (Some(filename), None) | (None, Some(filename)) => filename.clone(),
+        _ => usage(&args[0]),
+    };
+
+    Options {
+        seed,
+        max_errors,
+        stack_dump,
+        limits,
+        sandbox,
+        trace: trace.then_some(trace_options),
+        snapshot,
+        resume,
+        folded,
+        no_time,
+        lcov,
+        error_format,
+        filename,
+    }
+}
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        writeln!(io::stderr(), "Usage: {} <filename>", args[0]).unwrap();
-        std::process::exit(1);
+
+    match args.get(1).map(String::as_str) {
+        Some("test") => test(&args),
+        Some("check") => check(&args),
+        Some("lint") => lint(&args),
+        Some("profile") => profile(&args),
+        Some("coverage") => coverage(&args),
+        Some("debug") => debug(&args),
+        Some("fmt") => fmt(&args),
+        Some("highlight") => highlight(&args),
+        Some("replay") => replay(&args),
+        _ => run(&args),
+    }
+}
+
+/// Reads, tokenizes and compiles the program, printing every diagnostic.
+/// Exits if there are errors.
+fn load(options: &Options) -> (SourceMap, EvilStackVM) {
+    if let Some(path) = &options.resume {
+        return resume(options, path);
     }
 
-    let file_location = Path::new(&args[1]);
+    let file_location = Path::new(&options.filename);
     let file_path = Path::new(&current_dir().unwrap()).join(file_location);
 
     let mut file = File::open(file_path).expect("File not found");
@@ -22,14 +231,349 @@ fn main() {
     file.read_to_string(&mut contents)
         .expect("Failed to read file");
 
-    let mut tokenizer = tokenizer::Tokenizer::new();
-    tokenizer.tokenize(&contents);
-
-    let symbols = tokenizer.into_symbols();
+    let load = if options.sandbox {
+        include::load_sandboxed
+    } else {
+        include::load
+    };
+    let (sources, symbols, mut errors) = load(&options.filename, &contents, options.max_errors);
     // for symbol in &symbols {
     //     println!("{:?}", symbol);
     // }
 
-    let mut runtime = evilstack_vm::EvilStackVM::new(symbols);
-    runtime.execute();
+    let mut runtime = build(options, symbols, errors.len());
+    if let Some(trace) = &options.trace {
+        runtime = runtime.with_tracer(tracer(trace, &sources));
+    }
+
+    if let Err(compile_errors) = runtime.load() {
+        errors.extend(compile_errors);
+    }
+    errors.extend(runtime.warnings().iter().cloned());
+    if errors.iter().any(|error| error.severity == Severity::Error) {
+        report(options, &sources, errors);
+    }
+
+    if !errors.is_empty() {
+        error::print_all(errors, &sources, options.error_format);
+    }
+
+    (sources, runtime)
+}
+
+/// Reads a snapshot and restores its run. Exits if it cannot be restored.
+fn resume(options: &Options, path: &str) -> (SourceMap, EvilStackVM) {
+    let snapshot = match fs::read_to_string(path).map(|json| Snapshot::from_json(&json)) {
+        Ok(Ok(snapshot)) => snapshot,
+        Ok(Err(message)) => {
+            eprintln!("error: invalid snapshot {}: {}", path, message);
+            std::process::exit(1);
+        }
+        Err(error) => {
+            eprintln!("error: cannot read {}: {}", path, error);
+            std::process::exit(1);
+        }
+    };
+
+    let sources = snapshot.sources().clone();
+    let mut runtime = build(options, snapshot.symbols().to_vec(), 0);
+    if let Some(trace) = &options.trace {
+        runtime = runtime.with_tracer(tracer(trace, &sources));
+    }
+    if let Err(errors) = runtime.restore(&snapshot) {
+        report(options, &sources, errors);
+    }
+    (sources, runtime)
+}
+
+/// Creates a VM for `symbols` with the options, leaving room for the
+/// `reported` errors in the error limit.
+fn build(options: &Options, symbols: Vec<Symbol>, reported: usize) -> EvilStackVM {
+    let mut runtime = EvilStackVM::new(symbols)
+        .with_max_errors(options.max_errors.saturating_sub(reported).max(1))
+        .with_stack_dump(options.stack_dump)
+        .with_limits(options.limits.clone())
+        .with_sandbox(options.sandbox);
+    if let Some(seed) = options.seed {
+        runtime = runtime.with_seed(seed);
+    }
+    runtime
+}
+
+fn tracer(options: &TraceOptions, sources: &SourceMap) -> Tracer {
+    let out: Box<dyn Write> = match &options.file {
+        Some(file) => match File::create(file) {
+            Ok(file) => Box::new(file),
+            Err(error) => {
+                eprintln!("error: cannot create trace file {}: {}", file, error);
+                std::process::exit(1);
+            }
+        },
+        None => Box::new(io::stderr()),
+    };
+
+    let mut tracer = Tracer::new(out, options.format)
+        .with_every(options.every)
+        .with_sources(sources);
+    if let Some(label) = &options.label {
+        tracer = tracer.with_label(label);
+    }
+    tracer
+}
+
+fn run(args: &[String]) {
+    let options = parse_options(args, 1);
+    let (sources, mut runtime) = load(&options);
+
+    let result = runtime.execute();
+    if let Some(path) = &options.snapshot {
+        let snapshot = runtime.snapshot(&sources);
+        if let Err(error) = fs::write(path, snapshot.to_json()) {
+            eprintln!("error: cannot write {}: {}", path, error);
+            std::process::exit(1);
+        }
+    }
+    if let Err(errors) = result {
+        report(&options, &sources, errors);
+    }
+}
+
+fn check(args: &[String]) {
+    let options = parse_options(args, 2);
+    let (sources, runtime) = load(&options);
+
+    let errors = runtime.check_types();
+    if !errors.is_empty() {
+        report(&options, &sources, errors);
+    }
+}
+
+fn lint(args: &[String]) {
+    let options = parse_options(args, 2);
+    let (sources, runtime) = load(&options);
+
+    let warnings = runtime.lint();
+    if !warnings.is_empty() {
+        error::print_all(warnings, &sources, options.error_format);
+    }
+}
+
+fn profile(args: &[String]) {
+    let options = parse_options(args, 2);
+    let (sources, runtime) = load(&options);
+
+    let mut profiler = Profiler::new();
+    if options.no_time {
+        profiler = profiler.without_time();
+    }
+    let mut runtime = runtime.with_profiler(profiler);
+    let result = runtime.execute();
+    if let Err(errors) = &result {
+        error::print_all(errors.clone(), &sources, options.error_format);
+    }
+
+    let profiler = runtime.profiler().expect("the profiler was set");
+    eprint!("\n{}", profiler.report(&sources));
+
+    let folded = match options.folded.as_deref() {
+        Some("-") => None,
+        Some(path) => Some(PathBuf::from(path)),
+        None => Some(Path::new(&options.filename).with_extension("folded")),
+    };
+    match folded {
+        Some(folded) => {
+            if let Err(error) = fs::write(&folded, profiler.folded()) {
+                eprintln!("error: cannot write {}: {}", folded.display(), error);
+                std::process::exit(1);
+            }
+            eprintln!("\nFolded call stacks written to {}", folded.display());
+        }
+        None => print!("{}", profiler.folded()),
+    }
+
+    if result.is_err() {
+        std::process::exit(1);
+    }
+}
+
+/// Runs the program, and then every `test_` label in it on a fresh VM, and
+/// reports the coverage of all runs together.
+fn coverage(args: &[String]) {
+    let options = parse_options(args, 2);
+    let (sources, runtime) = load(&options);
+
+    let mut runtime = runtime.with_coverage(Coverage::new());
+    let mut failed = false;
+    if let Err(errors) = runtime.execute() {
+        error::print_all(errors, &sources, options.error_format);
+        failed = true;
+    }
+    let mut coverage = Coverage::new();
+    coverage.merge(runtime.coverage().expect("the coverage was recorded"));
+
+    let main = sources.get(0).expect("the main program is loaded");
+    for (label, _) in unit::test_labels(Path::new(&main.name), &main.source) {
+        let load = if options.sandbox {
+            include::load_sandboxed
+        } else {
+            include::load
+        };
+        let (_, symbols, _) = load(&main.name, &main.source, options.max_errors);
+
+        let mut runtime = build(&options, symbols, 0).with_coverage(Coverage::new());
+        if let Err(errors) = runtime.execute_label(&label) {
+            eprintln!("test {} failed:", label);
+            error::print_all(errors, &sources, options.error_format);
+            failed = true;
+        }
+        coverage.merge(runtime.coverage().expect("the coverage was recorded"));
+    }
+
+    eprint!("\n{}", coverage.annotated(&sources));
+
+    let lcov = match options.lcov.as_deref() {
+        Some("-") => None,
+        Some(path) => Some(PathBuf::from(path)),
+        None => Some(Path::new(&options.filename).with_extension("lcov")),
+    };
+    match lcov {
+        Some(lcov) => {
+            if let Err(error) = fs::write(&lcov, coverage.lcov(&sources)) {
+                eprintln!("error: cannot write {}: {}", lcov.display(), error);
+                std::process::exit(1);
+            }
+            eprintln!("\nCoverage written to {}", lcov.display());
+        }
+        None => print!("{}", coverage.lcov(&sources)),
+    }
+
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+fn debug(args: &[String]) {
+    let options = parse_options(args, 2);
+    let (sources, runtime) = load(&options);
+
+    let runtime = runtime.with_history(history::DEFAULT_CAPACITY);
+    debugger::run(runtime, &sources, options.error_format);
+}
+
+fn fmt(args: &[String]) {
+    let mut check = false;
+    let mut write = false;
+    let mut filenames: Vec<&String> = Vec::new();
+
+    for arg in args.iter().skip(2) {
+        match arg.as_str() {
+            "--check" => check = true,
+            "--write" => write = true,
+            _ => filenames.push(arg),
+        }
+    }
+
+    if filenames.is_empty() || (check && write) {
+        usage(&args[0]);
+    }
+
+    let mut failed = false;
+    for filename in filenames {
+        let contents = fs::read_to_string(filename).expect("Failed to read file");
+
+        let mut tokenizer = tokenizer::Tokenizer::new();
+        if let Err(errors) = tokenizer.tokenize(&contents) {
+            let sources = SourceMap::single(filename, &contents);
+            error::print_all(errors, &sources, ErrorFormat::Human);
+            failed = true;
+            continue;
+        }
+
+        let formatted = formatter::format(&tokenizer.tokens);
+        if check {
+            if formatted != contents {
+                eprintln!("{} is not formatted", filename);
+                failed = true;
+            }
+        } else if write {
+            if formatted != contents {
+                fs::write(filename, formatted).expect("Failed to write file");
+            }
+        } else {
+            print!("{}", formatted);
+        }
+    }
+
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+fn highlight(args: &[String]) {
+    let mut html = false;
+    let mut filename: Option<&String> = None;
+
+    for arg in args.iter().skip(2) {
+        match arg.as_str() {
+            "--html" => html = true,
+            _ if filename.is_none() => filename = Some(arg),
+            _ => usage(&args[0]),
+        }
+    }
+
+    let Some(filename) = filename else {
+        usage(&args[0]);
+    };
+
+    let contents = fs::read_to_string(filename).expect("Failed to read file");
+    let mut tokenizer = tokenizer::Tokenizer::new();
+    // Tokens that were recognized are highlighted even if others were not.
+    let _ = tokenizer.tokenize(&contents);
+
+    let tokens = tokenizer.semantic_tokens(&contents);
+    if html {
+        print!("{}", highlight::html(&contents, &tokens));
+    } else {
+        print!("{}", highlight::ansi(&contents, &tokens));
+    }
+}
+
+fn replay(args: &[String]) {
+    let [_, _, filename] = args else {
+        usage(&args[0]);
+    };
+
+    let file = File::open(filename).unwrap_or_else(|error| {
+        eprintln!("error: cannot open {}: {}", filename, error);
+        std::process::exit(1);
+    });
+    if let Err(error) = trace::replay(io::BufReader::new(file), &mut io::stdout().lock()) {
+        eprintln!("error: cannot replay {}: {}", filename, error);
+        std::process::exit(1);
+    }
+}
+
+fn report(options: &Options, sources: &SourceMap, errors: Vec<Error>) -> ! {
+    error::print_all(errors, sources, options.error_format);
+    std::process::exit(1);
+}
+
+fn test(args: &[String]) {
+    let mut bless = false;
+    let mut paths: Vec<PathBuf> = Vec::new();
+
+    for arg in args.iter().skip(2) {
+        match arg.as_str() {
+            "--bless" => bless = true,
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    if paths.is_empty() {
+        paths.push(PathBuf::from("tests"));
+    }
+
+    if !golden::run(&paths, bless) {
+        std::process::exit(1);
+    }
 }
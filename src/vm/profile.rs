@@ -0,0 +1,214 @@
+//! Execution profiles, written by `estack-vm profile`.
+//!
+//! Every executed instruction is counted, and timed, against its source line
+//! and against the innermost active call. The calls are kept as a tree of
+//! call paths from the top level, which becomes a folded-stack file for
+//! flamegraph tools. Loops in EvilStack are jumps that call themselves, so a
+//! label calling itself directly stays a single frame of its path.
+
+use crate::{error::SourceMap, evilstack_vm::Frame};
+use std::{collections::HashMap, time::Duration};
+
+/// Name of the root of every call path.
+const TOP_LEVEL: &str = "(top level)";
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Counter {
+    instructions: u64,
+    time: Duration,
+}
+
+impl Counter {
+    fn add(&mut self, time: Duration) {
+        self.instructions += 1;
+        self.time += time;
+    }
+}
+
+/// A call path: a node of the call tree.
+struct Node {
+    name: String,
+    parent: Option<usize>,
+    /// Instructions run by the innermost call of this path itself.
+    own: Counter,
+}
+
+pub struct Profiler {
+    /// Counters per file index and line.
+    lines: HashMap<(usize, usize), Counter>,
+    /// The call tree, with the top level at index `0`.
+    nodes: Vec<Node>,
+    children: HashMap<(usize, usize), usize>,
+    /// Call path of every active call, from the outermost: the node it runs
+    /// in and the callee's position in the program.
+    path: Vec<(usize, usize)>,
+    total: Counter,
+    /// Whether the report shows how long the instructions took.
+    timed: bool,
+}
+
+impl Default for Profiler {
+    fn default() -> Profiler {
+        Profiler::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler {
+            lines: HashMap::new(),
+            nodes: vec![Node {
+                name: TOP_LEVEL.to_string(),
+                parent: None,
+                own: Counter::default(),
+            }],
+            children: HashMap::new(),
+            path: Vec::new(),
+            total: Counter::default(),
+            timed: true,
+        }
+    }
+
+    /// Leaves the time column out of the report, which then is the same on
+    /// every run.
+    pub fn without_time(mut self) -> Profiler {
+        self.timed = false;
+        self
+    }
+
+    /// The node of the innermost active call.
+    fn current(&self) -> usize {
+        self.path.last().map_or(0, |(node, _)| *node)
+    }
+
+    /// Follows the active calls before an instruction runs. As a step makes
+    /// at most one call or unwinds calls, only the innermost ones can have
+    /// changed since the previous step.
+    pub(crate) fn enter<'a>(&mut self, calls: &[Frame], name: impl Fn(&Frame) -> &'a str) {
+        self.path.truncate(calls.len());
+        while let Some(frame) = calls.get(self.path.len()) {
+            let parent = self.current();
+            let node = match self.path.last() {
+                Some((_, callee)) if *callee == frame.callee => parent,
+                _ => *self
+                    .children
+                    .entry((parent, frame.callee))
+                    .or_insert_with(|| {
+                        self.nodes.push(Node {
+                            name: name(frame).to_string(),
+                            parent: Some(parent),
+                            own: Counter::default(),
+                        });
+                        self.nodes.len() - 1
+                    }),
+            };
+            self.path.push((node, frame.callee));
+        }
+    }
+
+    /// Counts an instruction at `file` and `line` that took `time`, against
+    /// the call entered last.
+    pub(crate) fn record(&mut self, file: usize, line: usize, time: Duration) {
+        let node = self.current();
+        self.nodes[node].own.add(time);
+        self.lines.entry((file, line)).or_default().add(time);
+        self.total.add(time);
+    }
+
+    /// Total instructions executed.
+    pub fn instructions(&self) -> u64 {
+        self.total.instructions
+    }
+
+    fn row(&self, counter: &Counter, what: &str) -> String {
+        let share = match self.total.instructions {
+            0 => 0.0,
+            total => counter.instructions as f64 * 100.0 / total as f64,
+        };
+        match self.timed {
+            true => format!(
+                "{:>12} {:>6.1}% {:>10.3}  {}\n",
+                counter.instructions,
+                share,
+                counter.time.as_secs_f64() * 1000.0,
+                what
+            ),
+            false => format!("{:>12} {:>6.1}%  {}\n", counter.instructions, share, what),
+        }
+    }
+
+    /// Renders the counters per source line and per subroutine as tables,
+    /// most executed first.
+    pub fn report(&self, sources: &SourceMap) -> String {
+        let header = match self.timed {
+            true => format!(
+                "{:>12} {:>7} {:>10}  {}\n",
+                "instructions", "%", "time (ms)", "location"
+            ),
+            false => format!("{:>12} {:>7}  {}\n", "instructions", "%", "location"),
+        };
+
+        let mut lines: Vec<(&(usize, usize), &Counter)> = self.lines.iter().collect();
+        lines.sort_by(|a, b| b.1.instructions.cmp(&a.1.instructions).then(a.0.cmp(b.0)));
+
+        let mut out = String::from("Lines\n");
+        out += &header;
+        for ((file, line), counter) in lines {
+            let file = sources.get(*file);
+            let name = file.map_or("", |file| file.name.as_str());
+            let text = file
+                .and_then(|file| file.source.lines().nth(line - 1))
+                .unwrap_or("")
+                .trim();
+            out += &self.row(counter, &format!("{}:{}  {}", name, line, text));
+        }
+
+        let mut subroutines: HashMap<&str, Counter> = HashMap::new();
+        for node in &self.nodes {
+            let counter = subroutines.entry(&node.name).or_default();
+            counter.instructions += node.own.instructions;
+            counter.time += node.own.time;
+        }
+        let mut subroutines: Vec<(&str, Counter)> = subroutines
+            .into_iter()
+            .filter(|(_, counter)| counter.instructions > 0)
+            .collect();
+        subroutines.sort_by(|a, b| b.1.instructions.cmp(&a.1.instructions).then(a.0.cmp(b.0)));
+
+        out += "\nSubroutines\n";
+        out += &header.replace("location", "subroutine");
+        for (name, counter) in subroutines {
+            out += &self.row(&counter, name);
+        }
+
+        out
+    }
+
+    /// Renders the call paths in the folded-stack format of flamegraph
+    /// tools: the frames from the top level joined by `;` and the number of
+    /// instructions run in that path itself, one path per line.
+    pub fn folded(&self) -> String {
+        let mut paths: Vec<String> = Vec::new();
+        for (i, node) in self.nodes.iter().enumerate() {
+            if node.own.instructions == 0 {
+                continue;
+            }
+
+            let mut frames = vec![node.name.as_str()];
+            let mut parent = self.nodes[i].parent;
+            while let Some(index) = parent {
+                frames.push(&self.nodes[index].name);
+                parent = self.nodes[index].parent;
+            }
+            frames.reverse();
+            paths.push(format!("{} {}", frames.join(";"), node.own.instructions));
+        }
+
+        paths.sort();
+        let mut out = paths.join("\n");
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out
+    }
+}
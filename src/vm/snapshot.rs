@@ -0,0 +1,360 @@
+//! Snapshots of a paused VM, written by `--snapshot-on-exit` and resumed by
+//! `--resume`.
+//!
+//! A snapshot holds the loaded program, as the files it was read from and
+//! the symbols they were tokenized into, so it resumes even if the files
+//! changed since. It also holds the state of the run: the instruction to run
+//! next, the stack, the flags, the active calls with their local variables,
+//! the top-level variables, the registers, the active `try` blocks and a
+//! seed for the random number generator. It is stored as JSON with spans as
+//! `[line, column, length, file, expansion]` and values as objects such as
+//! `{"integer": 5}`, `{"float": "2.5"}` and `{"string": "text"}`. Floats are
+//! strings so `NaN` and infinities survive.
+
+use crate::{
+    error::{SourceMap, Span},
+    evilstack_vm::{ConstType, Flags, Frame, Handler},
+    tokenizer::{Symbol, SymbolType},
+};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+
+const VERSION: u64 = 1;
+
+/// The state of a run, restored by [`crate::evilstack_vm::EvilStackVM::restore`].
+pub(crate) struct State {
+    pub ip: usize,
+    pub stack: Vec<ConstType>,
+    pub flags: Flags,
+    pub calls: Vec<Frame>,
+    pub locals: HashMap<String, ConstType>,
+    pub registers: Vec<Option<ConstType>>,
+    pub handlers: Vec<Handler>,
+    pub seed: u64,
+}
+
+pub struct Snapshot {
+    pub(crate) sources: SourceMap,
+    pub(crate) symbols: Vec<Symbol>,
+    pub(crate) state: State,
+}
+
+impl Snapshot {
+    /// The files the program was loaded from.
+    pub fn sources(&self) -> &SourceMap {
+        &self.sources
+    }
+
+    /// The symbols of the program, to create the VM that restores it.
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    pub fn to_json(&self) -> String {
+        let state = &self.state;
+        let files: Vec<Value> = self
+            .sources
+            .files()
+            .iter()
+            .map(|file| json!({ "name": file.name, "source": file.source }))
+            .collect();
+        let expansions: Vec<Value> = self
+            .sources
+            .expansions()
+            .iter()
+            .map(|expansion| {
+                json!({ "name": expansion.name, "call_site": span(&expansion.call_site) })
+            })
+            .collect();
+        let symbols: Vec<Value> = self
+            .symbols
+            .iter()
+            .map(|symbol| {
+                json!([
+                    symbol_type(&symbol.symbol_type),
+                    symbol.value,
+                    span(&symbol.span)
+                ])
+            })
+            .collect();
+
+        let flags = &state.flags;
+        let calls: Vec<Value> = state
+            .calls
+            .iter()
+            .map(|frame| {
+                json!({
+                    "return_address": frame.return_address,
+                    "callee": frame.callee,
+                    "call_site": span(&frame.call_site),
+                    "signature": frame.signature.map(|(args, results)| json!([args, results])),
+                    "base": frame.base,
+                    "locals": locals(&frame.locals),
+                })
+            })
+            .collect();
+        let handlers: Vec<Value> = state
+            .handlers
+            .iter()
+            .map(|handler| json!([handler.target, handler.depth, handler.calls]))
+            .collect();
+
+        json!({
+            "version": VERSION,
+            "files": files,
+            "expansions": expansions,
+            "symbols": symbols,
+            "ip": state.ip,
+            "stack": state.stack.iter().map(value).collect::<Vec<Value>>(),
+            "flags": {
+                "zero": flags.zero,
+                "negative": flags.negative,
+                "equal": flags.equal,
+                "not_equal": flags.not_equal,
+                "greater_than": flags.greater_than,
+                "less_than": flags.less_than,
+                "greater_than_or_equal": flags.greater_than_or_equal,
+                "less_than_or_equal": flags.less_than_or_equal,
+            },
+            "calls": calls,
+            "locals": locals(&state.locals),
+            "registers": state
+                .registers
+                .iter()
+                .map(|register| register.as_ref().map_or(Value::Null, value))
+                .collect::<Vec<Value>>(),
+            "handlers": handlers,
+            "seed": state.seed,
+        })
+        .to_string()
+    }
+
+    /// Reads a snapshot written by [`Snapshot::to_json`]. Whether it fits
+    /// its program is checked when it is restored.
+    pub fn from_json(text: &str) -> Result<Snapshot, String> {
+        let root: Value =
+            serde_json::from_str(text).map_err(|error| format!("invalid JSON: {}", error))?;
+        if field(&root, "version")?.as_u64() != Some(VERSION) {
+            return Err("unsupported snapshot version".to_string());
+        }
+
+        let mut sources = SourceMap::default();
+        for file in array(field(&root, "files")?)? {
+            sources.add(
+                string(field(file, "name")?)?,
+                string(field(file, "source")?)?,
+            );
+        }
+        for expansion in array(field(&root, "expansions")?)? {
+            let call_site = read_span(field(expansion, "call_site")?)?;
+            sources.add_expansion(string(field(expansion, "name")?)?, call_site);
+        }
+
+        let mut symbols = Vec::new();
+        for symbol in array(field(&root, "symbols")?)? {
+            let [symbol_type, value, span] = array(symbol)? else {
+                return Err("invalid symbol".to_string());
+            };
+            symbols.push(Symbol::new(
+                read_symbol_type(string(symbol_type)?)?,
+                string(value)?.to_string(),
+                read_span(span)?,
+            ));
+        }
+
+        let flags = field(&root, "flags")?;
+        let flag = |name: &str| {
+            field(flags, name)?
+                .as_bool()
+                .ok_or(format!("`{}` is not a boolean", name))
+        };
+        let flags = Flags {
+            zero: flag("zero")?,
+            negative: flag("negative")?,
+            equal: flag("equal")?,
+            not_equal: flag("not_equal")?,
+            greater_than: flag("greater_than")?,
+            less_than: flag("less_than")?,
+            greater_than_or_equal: flag("greater_than_or_equal")?,
+            less_than_or_equal: flag("less_than_or_equal")?,
+        };
+
+        let mut calls = Vec::new();
+        for frame in array(field(&root, "calls")?)? {
+            let signature = match field(frame, "signature")? {
+                Value::Null => None,
+                signature => match array(signature)? {
+                    [args, results] => Some((number(args)?, number(results)?)),
+                    _ => return Err("invalid signature".to_string()),
+                },
+            };
+            calls.push(Frame {
+                return_address: number(field(frame, "return_address")?)?,
+                callee: number(field(frame, "callee")?)?,
+                call_site: read_span(field(frame, "call_site")?)?,
+                signature,
+                base: number(field(frame, "base")?)?,
+                locals: read_locals(field(frame, "locals")?)?,
+            });
+        }
+
+        let mut handlers = Vec::new();
+        for handler in array(field(&root, "handlers")?)? {
+            let [target, depth, calls] = array(handler)? else {
+                return Err("invalid try block".to_string());
+            };
+            handlers.push(Handler {
+                target: number(target)?,
+                depth: number(depth)?,
+                calls: number(calls)?,
+            });
+        }
+
+        let state = State {
+            ip: number(field(&root, "ip")?)?,
+            stack: array(field(&root, "stack")?)?
+                .iter()
+                .map(read_value)
+                .collect::<Result<_, _>>()?,
+            flags,
+            calls,
+            locals: read_locals(field(&root, "locals")?)?,
+            registers: array(field(&root, "registers")?)?
+                .iter()
+                .map(|register| match register {
+                    Value::Null => Ok(None),
+                    register => read_value(register).map(Some),
+                })
+                .collect::<Result<_, _>>()?,
+            handlers,
+            seed: field(&root, "seed")?
+                .as_u64()
+                .ok_or("`seed` is not a number")?,
+        };
+
+        Ok(Snapshot {
+            sources,
+            symbols,
+            state,
+        })
+    }
+}
+
+fn span(span: &Span) -> Value {
+    json!([
+        span.line,
+        span.column,
+        span.length,
+        span.file,
+        span.expansion
+    ])
+}
+
+fn symbol_type(symbol_type: &SymbolType) -> &'static str {
+    match symbol_type {
+        SymbolType::Instruction => "instruction",
+        SymbolType::Label => "label",
+        SymbolType::LabelReference => "label_reference",
+        SymbolType::Integer => "integer",
+        SymbolType::Float => "float",
+        SymbolType::String => "string",
+        SymbolType::Punctuation => "punctuation",
+    }
+}
+
+fn value(value: &ConstType) -> Value {
+    match value {
+        ConstType::Integer(i) => json!({ "integer": i }),
+        ConstType::Float(f) => json!({ "float": f.to_string() }),
+        ConstType::String(s) => json!({ "string": s }),
+    }
+}
+
+fn locals(locals: &HashMap<String, ConstType>) -> Value {
+    // Sorted, so the same state always gives the same snapshot.
+    let mut names: Vec<&String> = locals.keys().collect();
+    names.sort();
+    let locals: Map<String, Value> = names
+        .into_iter()
+        .map(|name| (name.clone(), value(&locals[name])))
+        .collect();
+    Value::Object(locals)
+}
+
+fn field<'a>(value: &'a Value, name: &str) -> Result<&'a Value, String> {
+    value.get(name).ok_or(format!("missing `{}`", name))
+}
+
+fn array(value: &Value) -> Result<&[Value], String> {
+    value
+        .as_array()
+        .map(Vec::as_slice)
+        .ok_or(format!("expected an array, found {}", value))
+}
+
+fn string(value: &Value) -> Result<&str, String> {
+    value
+        .as_str()
+        .ok_or(format!("expected a string, found {}", value))
+}
+
+fn number(value: &Value) -> Result<usize, String> {
+    value
+        .as_u64()
+        .map(|number| number as usize)
+        .ok_or(format!("expected a number, found {}", value))
+}
+
+fn read_span(value: &Value) -> Result<Span, String> {
+    match array(value)? {
+        [line, column, length, file, expansion] => Ok(Span {
+            line: number(line)?,
+            column: number(column)?,
+            length: number(length)?,
+            file: number(file)?,
+            expansion: number(expansion)?,
+        }),
+        _ => Err(format!("invalid span {}", value)),
+    }
+}
+
+fn read_symbol_type(name: &str) -> Result<SymbolType, String> {
+    match name {
+        "instruction" => Ok(SymbolType::Instruction),
+        "label" => Ok(SymbolType::Label),
+        "label_reference" => Ok(SymbolType::LabelReference),
+        "integer" => Ok(SymbolType::Integer),
+        "float" => Ok(SymbolType::Float),
+        "string" => Ok(SymbolType::String),
+        "punctuation" => Ok(SymbolType::Punctuation),
+        _ => Err(format!("unknown symbol type `{}`", name)),
+    }
+}
+
+fn read_value(value: &Value) -> Result<ConstType, String> {
+    let invalid = || format!("invalid value {}", value);
+    let object = value.as_object().ok_or_else(invalid)?;
+    match object.iter().next() {
+        Some((tag, Value::Number(i))) if tag == "integer" && object.len() == 1 => i
+            .as_i64()
+            .and_then(|i| i32::try_from(i).ok())
+            .map(ConstType::Integer)
+            .ok_or_else(invalid),
+        Some((tag, Value::String(f))) if tag == "float" && object.len() == 1 => {
+            f.parse().map(ConstType::Float).map_err(|_| invalid())
+        }
+        Some((tag, Value::String(s))) if tag == "string" && object.len() == 1 => {
+            Ok(ConstType::String(s.clone()))
+        }
+        _ => Err(invalid()),
+    }
+}
+
+fn read_locals(value: &Value) -> Result<HashMap<String, ConstType>, String> {
+    value
+        .as_object()
+        .ok_or(format!("expected an object, found {}", value))?
+        .iter()
+        .map(|(name, value)| Ok((name.clone(), read_value(value)?)))
+        .collect()
+}
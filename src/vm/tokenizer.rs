@@ -1,8 +1,9 @@
 // use regex::Regex;
 
-use crate::error::Error;
+use crate::error::{Error, Span, DEFAULT_MAX_ERRORS};
+use std::ops::Range;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum SymbolType {
     Instruction,
     Label,
@@ -10,37 +11,33 @@ pub enum SymbolType {
     Integer,
     Float,
     String,
+    Punctuation,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Symbol {
     pub symbol_type: SymbolType,
     pub value: String,
-    pub line_number: usize,
-    pub column_number: usize,
+    pub span: Span,
 }
 
 impl Symbol {
-    pub fn new(
-        symbol_type: SymbolType,
-        value: String,
-        line_number: usize,
-        column_number: usize,
-    ) -> Symbol {
+    pub fn new(symbol_type: SymbolType, value: String, span: Span) -> Symbol {
         Symbol {
             symbol_type,
             value,
-            line_number,
-            column_number,
+            span,
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum TokenType {
     StringLiteral,
     NumericLiteral,
     Identifier,
+    /// `=` in a `const` declaration.
+    Punctuation,
     Comment,
     Unknown,
     EOF,
@@ -50,14 +47,55 @@ pub enum TokenType {
 pub struct Token {
     pub token_type: TokenType,
     pub value: String,
-    pub line_number: usize,
-    pub column_number: usize,
+    pub span: Span,
+}
+
+/// What a token means, for syntax highlighting.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SemanticTokenType {
+    Instruction,
+    LabelDefinition,
+    LabelReference,
+    Number,
+    String,
+    Comment,
+}
+
+impl SemanticTokenType {
+    /// A short kebab-case name, used for example as a CSS class.
+    pub fn name(&self) -> &'static str {
+        match self {
+            SemanticTokenType::Instruction => "instruction",
+            SemanticTokenType::LabelDefinition => "label-definition",
+            SemanticTokenType::LabelReference => "label-reference",
+            SemanticTokenType::Number => "number",
+            SemanticTokenType::String => "string",
+            SemanticTokenType::Comment => "comment",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SemanticToken {
+    pub token_type: SemanticTokenType,
+    /// Byte range in the tokenized source, including the quotes of strings
+    /// and the `;` of comments.
+    pub range: Range<usize>,
+    pub span: Span,
 }
 
 pub struct Tokenizer {
     pub tokens: Vec<Token>,
     line_number: usize,
     column_number: usize,
+    max_errors: usize,
+    file: usize,
+}
+
+impl Default for Tokenizer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Tokenizer {
@@ -66,21 +104,61 @@ impl Tokenizer {
             tokens: Vec::new(),
             line_number: 1,
             column_number: 1,
+            max_errors: DEFAULT_MAX_ERRORS,
+            file: 0,
         }
     }
 
-    pub fn tokenize(&mut self, code: &str) {
+    /// Sets the index of the file being tokenized, stored in every span.
+    pub fn with_file(mut self, file: usize) -> Tokenizer {
+        self.file = file;
+        self
+    }
+
+    /// Sets how many errors are collected before tokenizing stops.
+    pub fn with_max_errors(mut self, max_errors: usize) -> Tokenizer {
+        self.max_errors = max_errors;
+        self
+    }
+
+    /// Splits `code` into tokens. Invalid characters and unterminated
+    /// strings are skipped so that every error in the file is reported, but
+    /// the tokens that were recognized are kept either way. Comments are
+    /// kept as `Comment` tokens holding the text after the `;`.
+    pub fn tokenize(&mut self, code: &str) -> Result<(), Vec<Error>> {
         let mut curr_token = String::new();
         let mut token_type = TokenType::Unknown;
+        let mut token_start = (self.line_number, self.column_number);
+        let mut errors = Vec::new();
+
+        let mut chars = code.chars().peekable();
+        while let Some(c) = chars.next() {
+            if errors.len() >= self.max_errors {
+                break;
+            }
 
-        for c in code.chars() {
             if token_type == TokenType::Comment {
                 if c == '\n' {
+                    self.tokens.push(Token {
+                        token_type: TokenType::Comment,
+                        value: curr_token.clone(),
+                        span: self.span_from(token_start, 0),
+                    });
+                    curr_token.clear();
                     token_type = TokenType::Unknown;
+                    self.line_number += 1;
+                    self.column_number = 1;
+                } else {
+                    curr_token.push(c);
+                    self.column_number += 1;
                 }
                 continue;
             }
 
+            if token_type == TokenType::Unknown && curr_token.is_empty() {
+                token_start = (self.line_number, self.column_number);
+            }
+
             match c {
                 '0'..='9' => {
                     if token_type == TokenType::Unknown {
@@ -90,17 +168,30 @@ impl Tokenizer {
                     curr_token.push(c);
                 }
                 '.' => match token_type {
-                    TokenType::NumericLiteral | TokenType::StringLiteral => {
+                    TokenType::NumericLiteral
+                    | TokenType::StringLiteral
+                    | TokenType::Identifier => {
+                        curr_token.push(c);
+                    }
+                    // Directives such as `.data` start with a dot, and floats
+                    // such as `.5` may.
+                    TokenType::Unknown
+                        if curr_token.is_empty()
+                            && chars.peek().is_some_and(|next| next.is_alphanumeric()) =>
+                    {
+                        token_type = match chars.peek() {
+                            Some(next) if next.is_ascii_digit() => TokenType::NumericLiteral,
+                            _ => TokenType::Identifier,
+                        };
                         curr_token.push(c);
                     }
                     _ => {
                         // panic!("Invalid token: {}", c);
-                        Error::new(
+                        errors.push(Error::new(
+                            "E0001",
                             "Invalid token",
-                            format!("{}:{}", self.line_number, self.column_number),
-                        )
-                        .print();
-                        return;
+                            Span::new(self.line_number, self.column_number, 1),
+                        ));
                     }
                 },
                 '"' => {
@@ -110,8 +201,7 @@ impl Tokenizer {
                         self.tokens.push(Token {
                             token_type: TokenType::StringLiteral,
                             value: curr_token.clone(),
-                            line_number: self.line_number.clone(),
-                            column_number: self.column_number.clone(),
+                            span: self.span_from(token_start, 1),
                         });
                         curr_token.clear();
                         token_type = TokenType::Unknown;
@@ -124,47 +214,47 @@ impl Tokenizer {
 
                     curr_token.push(c);
                 }
+                ';' | '=' if token_type == TokenType::StringLiteral => {
+                    curr_token.push(c);
+                }
+                '=' => {
+                    self.flush(&mut curr_token, token_type, token_start);
+                    token_type = TokenType::Unknown;
+                    self.tokens.push(Token {
+                        token_type: TokenType::Punctuation,
+                        value: c.to_string(),
+                        span: Span::new(self.line_number, self.column_number, 1),
+                    });
+                }
                 ';' => {
+                    self.flush(&mut curr_token, token_type, token_start);
+                    token_start = (self.line_number, self.column_number);
                     token_type = TokenType::Comment;
                 }
                 ' ' | '\n' | '\t' => {
-                    curr_token = curr_token.trim().to_string();
-
-                    if !curr_token.is_empty() {
-                        match token_type {
-                            TokenType::NumericLiteral => {
-                                self.tokens.push(Token {
-                                    token_type: TokenType::NumericLiteral,
-                                    value: curr_token.clone(),
-                                    line_number: self.line_number.clone(),
-                                    column_number: self.column_number.clone(),
-                                });
-                            }
-                            TokenType::Identifier => {
-                                self.tokens.push(Token {
-                                    token_type: TokenType::Identifier,
-                                    value: curr_token.clone(),
-                                    line_number: self.line_number.clone(),
-                                    column_number: self.column_number.clone(),
-                                });
-                            }
-                            TokenType::StringLiteral => {
-                                if c == '\n' {
-                                    // panic!("Unterminated string literal: {}", curr_token);
-                                    Error::new(
-                                        "Unterminated string literal",
-                                        format!("{}:{}", self.line_number, self.column_number),
-                                    )
-                                    .print();
-                                    return;
-                                }
-
-                                curr_token.push(c);
-                                continue;
-                            }
-                            _ => {}
+                    if token_type == TokenType::StringLiteral {
+                        if c == '\n' {
+                            // panic!("Unterminated string literal: {}", curr_token);
+                            errors.push(Error::new(
+                                "E0002",
+                                "Unterminated string literal",
+                                self.span_from(token_start, 0),
+                            ));
+
+                            // Keep the string so the instruction it belongs to
+                            // is not also reported as missing an argument.
+                            self.tokens.push(Token {
+                                token_type: TokenType::StringLiteral,
+                                value: curr_token.clone(),
+                                span: self.span_from(token_start, 0),
+                            });
+                            curr_token.clear();
+                            token_type = TokenType::Unknown;
+                        } else {
+                            curr_token.push(c);
                         }
-                        curr_token.clear();
+                    } else if !curr_token.is_empty() {
+                        self.flush(&mut curr_token, token_type, token_start);
                         token_type = TokenType::Unknown;
                     }
                 }
@@ -181,12 +271,68 @@ impl Tokenizer {
             }
         }
 
+        match token_type {
+            TokenType::StringLiteral if errors.len() < self.max_errors => {
+                errors.push(Error::new(
+                    "E0002",
+                    "Unterminated string literal",
+                    self.span_from(token_start, 0),
+                ));
+            }
+            TokenType::Comment => {
+                self.tokens.push(Token {
+                    token_type,
+                    value: curr_token,
+                    span: self.span_from(token_start, 0),
+                });
+            }
+            _ => self.flush(&mut curr_token, token_type, token_start),
+        }
+
         self.tokens.push(Token {
             token_type: TokenType::EOF,
             value: String::new(),
-            line_number: self.line_number,
-            column_number: self.column_number,
+            span: Span::new(self.line_number, self.column_number, 0),
         });
+
+        for token in &mut self.tokens {
+            token.span.file = self.file;
+        }
+        for error in &mut errors {
+            if let Some(span) = &mut error.span {
+                span.file = self.file;
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Pushes the number or identifier collected in `curr_token`, if any,
+    /// and clears it.
+    fn flush(&mut self, curr_token: &mut String, token_type: TokenType, start: (usize, usize)) {
+        if matches!(
+            token_type,
+            TokenType::NumericLiteral | TokenType::Identifier
+        ) && !curr_token.is_empty()
+        {
+            self.tokens.push(Token {
+                token_type,
+                value: curr_token.clone(),
+                span: self.span_from(start, 0),
+            });
+        }
+        curr_token.clear();
+    }
+
+    /// Returns the span from `start` up to the current column, plus `extra`
+    /// characters.
+    fn span_from(&self, start: (usize, usize), extra: usize) -> Span {
+        let (line, column) = start;
+        Span::new(line, column, self.column_number - column + extra)
     }
 
     pub fn into_symbols(&self) -> Vec<Symbol> {
@@ -199,22 +345,19 @@ impl Tokenizer {
                         symbols.push(Symbol::new(
                             SymbolType::Float,
                             token.value.clone() + "0",
-                            token.line_number,
-                            token.column_number,
+                            token.span,
                         ));
                     } else if token.value.contains('.') {
                         symbols.push(Symbol::new(
                             SymbolType::Float,
                             token.value.clone(),
-                            token.line_number,
-                            token.column_number,
+                            token.span,
                         ));
                     } else {
                         symbols.push(Symbol::new(
                             SymbolType::Integer,
                             token.value.clone(),
-                            token.line_number,
-                            token.column_number,
+                            token.span,
                         ));
                     }
                 }
@@ -223,22 +366,19 @@ impl Tokenizer {
                         symbols.push(Symbol::new(
                             SymbolType::Label,
                             token.value.clone(),
-                            token.line_number,
-                            token.column_number,
+                            token.span,
                         ));
                     } else if token.value.starts_with('@') {
                         symbols.push(Symbol::new(
                             SymbolType::LabelReference,
                             token.value.clone(),
-                            token.line_number,
-                            token.column_number,
+                            token.span,
                         ));
                     } else {
                         symbols.push(Symbol::new(
                             SymbolType::Instruction,
                             token.value.clone(),
-                            token.line_number,
-                            token.column_number,
+                            token.span,
                         ));
                     }
                 }
@@ -246,8 +386,14 @@ impl Tokenizer {
                     symbols.push(Symbol::new(
                         SymbolType::String,
                         token.value.clone(),
-                        token.line_number,
-                        token.column_number,
+                        token.span,
+                    ));
+                }
+                TokenType::Punctuation => {
+                    symbols.push(Symbol::new(
+                        SymbolType::Punctuation,
+                        token.value.clone(),
+                        token.span,
                     ));
                 }
                 _ => {}
@@ -256,4 +402,50 @@ impl Tokenizer {
 
         symbols
     }
+
+    /// Classifies the tokens of `code`, which must be the source this
+    /// tokenizer was run on, in source order.
+    pub fn semantic_tokens(&self, code: &str) -> Vec<SemanticToken> {
+        let line_starts: Vec<usize> = std::iter::once(0)
+            .chain(code.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+
+        // Converts a 1-based line and character column to a byte offset.
+        let offset = |line: usize, column: usize| -> usize {
+            let start = line_starts.get(line - 1).copied().unwrap_or(code.len());
+            code[start..]
+                .char_indices()
+                .nth(column - 1)
+                .map(|(i, _)| start + i)
+                .unwrap_or(code.len())
+        };
+
+        let mut tokens = Vec::new();
+        for token in &self.tokens {
+            let token_type = match token.token_type {
+                TokenType::StringLiteral => SemanticTokenType::String,
+                TokenType::NumericLiteral => SemanticTokenType::Number,
+                TokenType::Comment => SemanticTokenType::Comment,
+                TokenType::Identifier if token.value.ends_with(':') => {
+                    SemanticTokenType::LabelDefinition
+                }
+                TokenType::Identifier if token.value.starts_with('@') => {
+                    SemanticTokenType::LabelReference
+                }
+                TokenType::Identifier => SemanticTokenType::Instruction,
+                _ => continue,
+            };
+
+            let span = token.span;
+            let start = offset(span.line, span.column);
+            let end = offset(span.line, span.column + span.length);
+            tokens.push(SemanticToken {
+                token_type,
+                range: start..end,
+                span,
+            });
+        }
+
+        tokens
+    }
 }
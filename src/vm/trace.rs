@@ -0,0 +1,338 @@
+//! Instruction-level execution traces, written by `--trace`.
+//!
+//! The text format has one line per executed instruction, including one that
+//! raised an error, with tab-separated step number, instruction index,
+//! source position, instruction and the stack after it ran, bottom first,
+//! such as `3`, `2`, `main.estk:3:1`, `add` and `[5]`.
+//!
+//! The binary format holds the same information more compactly and is turned
+//! back into text by [`replay`]. It starts with the magic bytes `ESTRACE1`,
+//! the names of the program's files and a table of its instructions with
+//! their positions as file index, line and column, followed
+//! by one record per step: the step number, the instruction index, how many
+//! values were removed from the top of the stack since the previous record
+//! and the values pushed in their place. Numbers are LEB128 varints; values
+//! are a tag byte (`0` integer as a zigzag varint, `1` float as 4 bytes
+//! little endian, `2` string as a length and UTF-8 bytes) and their data.
+
+use crate::{
+    error::{SourceMap, Span},
+    evilstack_vm::{ConstType, Instruction},
+};
+use std::io::{self, BufWriter, Read, Write};
+
+const MAGIC: &[u8; 8] = b"ESTRACE1";
+
+/// Most files or instructions a binary trace's tables may hold, so a
+/// corrupt count is reported instead of read until the input ends.
+const MAX_TABLE: u64 = 1 << 24;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceFormat {
+    Text,
+    Binary,
+}
+
+/// Writes the steps of a run, optionally only those inside a call to a
+/// label or every Nth step.
+pub struct Tracer {
+    out: BufWriter<Box<dyn Write>>,
+    format: TraceFormat,
+    label: Option<String>,
+    every: u64,
+    /// Names of the program's files, by index.
+    files: Vec<String>,
+    /// The stack at the previous record, for the binary format.
+    stack: Vec<ConstType>,
+    started: bool,
+}
+
+impl Tracer {
+    pub fn new(out: Box<dyn Write>, format: TraceFormat) -> Tracer {
+        Tracer {
+            out: BufWriter::new(out),
+            format,
+            label: None,
+            every: 1,
+            files: Vec::new(),
+            stack: Vec::new(),
+            started: false,
+        }
+    }
+
+    /// Only traces steps taken while a call to `label` is active, including
+    /// the subroutines it calls.
+    pub fn with_label(mut self, label: &str) -> Tracer {
+        self.label = Some(label.to_string());
+        self
+    }
+
+    /// Only traces every `every`th step, starting with the first.
+    pub fn with_every(mut self, every: u64) -> Tracer {
+        self.every = every.max(1);
+        self
+    }
+
+    /// Names positions after the files of `sources`.
+    pub fn with_sources(mut self, sources: &SourceMap) -> Tracer {
+        self.files = sources
+            .files()
+            .iter()
+            .map(|file| file.name.clone())
+            .collect();
+        self
+    }
+
+    pub(crate) fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    /// Whether step number `step`, counted from `1`, is traced.
+    pub(crate) fn wants(&self, step: u64) -> bool {
+        (step - 1).is_multiple_of(self.every)
+    }
+
+    /// Writes the header of the binary format. Called once before the first
+    /// step.
+    pub(crate) fn start(&mut self, program: &[Instruction]) {
+        if self.started {
+            return;
+        }
+        self.started = true;
+
+        if self.format == TraceFormat::Binary {
+            let mut header = MAGIC.to_vec();
+            write_varint(&mut header, self.files.len() as u64);
+            for file in &self.files {
+                write_string(&mut header, file);
+            }
+            write_varint(&mut header, program.len() as u64);
+            for instruction in program {
+                let pos = instruction.pos();
+                write_varint(&mut header, pos.file as u64);
+                write_varint(&mut header, pos.line as u64);
+                write_varint(&mut header, pos.column as u64);
+                write_string(&mut header, &instruction.to_string());
+            }
+            let _ = self.out.write_all(&header);
+        }
+    }
+
+    pub(crate) fn record(
+        &mut self,
+        step: u64,
+        ip: usize,
+        instruction: &Instruction,
+        stack: &[ConstType],
+    ) {
+        match self.format {
+            TraceFormat::Text => {
+                let pos = instruction.pos();
+                let file = self.files.get(pos.file).map_or("", String::as_str);
+                let line = line(step, ip, file, pos, &instruction.to_string(), stack);
+                let _ = self.out.write_all(line.as_bytes());
+            }
+            TraceFormat::Binary => {
+                let kept = self
+                    .stack
+                    .iter()
+                    .zip(stack)
+                    .take_while(|(a, b)| same(a, b))
+                    .count();
+
+                let mut record = Vec::new();
+                write_varint(&mut record, step);
+                write_varint(&mut record, ip as u64);
+                write_varint(&mut record, (self.stack.len() - kept) as u64);
+                write_varint(&mut record, (stack.len() - kept) as u64);
+                for value in &stack[kept..] {
+                    write_value(&mut record, value);
+                }
+                let _ = self.out.write_all(&record);
+
+                self.stack.truncate(kept);
+                self.stack.extend_from_slice(&stack[kept..]);
+            }
+        }
+    }
+
+    pub(crate) fn flush(&mut self) {
+        let _ = self.out.flush();
+    }
+}
+
+/// Formats a step of the text format.
+fn line(step: u64, ip: usize, file: &str, pos: Span, text: &str, stack: &[ConstType]) -> String {
+    let stack: Vec<String> = stack.iter().map(ConstType::to_string).collect();
+    format!(
+        "{}\t{}\t{}:{}\t{}\t[{}]\n",
+        step,
+        ip,
+        file,
+        pos,
+        text,
+        stack.join(", ")
+    )
+}
+
+/// Whether both values are the same, unlike `==` which compares integers
+/// and floats by value.
+fn same(a: &ConstType, b: &ConstType) -> bool {
+    match (a, b) {
+        (ConstType::Integer(a), ConstType::Integer(b)) => a == b,
+        (ConstType::Float(a), ConstType::Float(b)) => a.to_bits() == b.to_bits(),
+        (ConstType::String(a), ConstType::String(b)) => a == b,
+        _ => false,
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn write_string(out: &mut Vec<u8>, s: &str) {
+    write_varint(out, s.len() as u64);
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn write_value(out: &mut Vec<u8>, value: &ConstType) {
+    match value {
+        ConstType::Integer(i) => {
+            out.push(0);
+            write_varint(out, ((i << 1) ^ (i >> 31)) as u32 as u64);
+        }
+        ConstType::Float(f) => {
+            out.push(1);
+            out.extend_from_slice(&f.to_le_bytes());
+        }
+        ConstType::String(s) => {
+            out.push(2);
+            write_string(out, s);
+        }
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Reads the binary trace format.
+struct Reader<R: Read> {
+    input: R,
+}
+
+impl<R: Read> Reader<R> {
+    fn byte(&mut self) -> io::Result<u8> {
+        let mut byte = [0];
+        self.input.read_exact(&mut byte)?;
+        Ok(byte[0])
+    }
+
+    fn varint(&mut self) -> io::Result<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(invalid("varint is too long"))
+    }
+
+    /// Reads the length of a table, at most [`MAX_TABLE`].
+    fn table(&mut self) -> io::Result<u64> {
+        match self.varint()? {
+            len if len > MAX_TABLE => Err(invalid("table is too long")),
+            len => Ok(len),
+        }
+    }
+
+    /// Reads a string, allocating only as much as the input holds.
+    fn string(&mut self) -> io::Result<String> {
+        let len = self.varint()?;
+        let mut bytes = Vec::new();
+        (&mut self.input).take(len).read_to_end(&mut bytes)?;
+        if bytes.len() as u64 != len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        String::from_utf8(bytes).map_err(|_| invalid("string is not UTF-8"))
+    }
+
+    fn value(&mut self) -> io::Result<ConstType> {
+        match self.byte()? {
+            0 => {
+                let zigzag = self.varint()? as u32;
+                Ok(ConstType::Integer(
+                    ((zigzag >> 1) as i32) ^ -((zigzag & 1) as i32),
+                ))
+            }
+            1 => {
+                let mut bytes = [0; 4];
+                self.input.read_exact(&mut bytes)?;
+                Ok(ConstType::Float(f32::from_le_bytes(bytes)))
+            }
+            2 => Ok(ConstType::String(self.string()?)),
+            _ => Err(invalid("unknown value tag")),
+        }
+    }
+}
+
+/// Converts a trace in the binary format to the text format.
+pub fn replay(input: impl Read, out: &mut impl Write) -> io::Result<()> {
+    let mut reader = Reader { input };
+
+    let mut magic = [0; 8];
+    reader.input.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid("not a binary trace"));
+    }
+
+    let mut files = Vec::new();
+    for _ in 0..reader.table()? {
+        files.push(reader.string()?);
+    }
+
+    let mut program = Vec::new();
+    for _ in 0..reader.table()? {
+        let file = reader.varint()? as usize;
+        let line = reader.varint()? as usize;
+        let column = reader.varint()? as usize;
+        let pos = Span {
+            line,
+            column,
+            ..Span::default()
+        };
+        program.push((
+            files.get(file).cloned().unwrap_or_default(),
+            pos,
+            reader.string()?,
+        ));
+    }
+
+    let mut stack = Vec::new();
+    loop {
+        let step = match reader.varint() {
+            Ok(step) => step,
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(error) => return Err(error),
+        };
+        let ip = reader.varint()? as usize;
+        let popped = reader.varint()? as usize;
+        let pushed = reader.varint()?;
+
+        stack.truncate(stack.len().saturating_sub(popped));
+        for _ in 0..pushed {
+            stack.push(reader.value()?);
+        }
+
+        let Some((file, pos, text)) = program.get(ip) else {
+            return Err(invalid("instruction index out of range"));
+        };
+        out.write_all(line(step, ip, file, *pos, text, &stack).as_bytes())?;
+    }
+}
@@ -0,0 +1,290 @@
+//! Static type inference for stack slots, used by `estack-vm check`.
+//!
+//! The checker follows every path through the program with an abstract
+//! stack holding the `ConstType` variant of each slot, calling into jump
+//! targets the same way the VM does, and reports instructions whose operand
+//! types can never work. After a mismatch the result is treated as unknown
+//! so the rest of the path is still checked.
+
+use crate::{
+    error::Error,
+    evilstack_vm::{ConstType, Instruction},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+/// Paths whose return stack grows deeper than this are not followed further
+/// (loops written as recursive jumps).
+const MAX_CALL_DEPTH: usize = 16;
+
+/// Upper bound on the number of abstract states explored.
+const MAX_STATES: usize = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Type {
+    Integer,
+    Float,
+    String,
+    /// The value is not known statically (below the analyzed stack, or
+    /// produced by an unimplemented instruction).
+    Unknown,
+}
+
+impl Type {
+    fn of(value: &ConstType) -> Type {
+        match value {
+            ConstType::Integer(_) => Type::Integer,
+            ConstType::Float(_) => Type::Float,
+            ConstType::String(_) => Type::String,
+        }
+    }
+
+    fn is_numeric(&self) -> bool {
+        matches!(self, Type::Integer | Type::Float)
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Integer => write!(f, "integer"),
+            Type::Float => write!(f, "float"),
+            Type::String => write!(f, "string"),
+            Type::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct State {
+    ip: usize,
+    stack: Vec<Type>,
+    return_stack: Vec<usize>,
+}
+
+/// Result type of a binary arithmetic instruction, mirroring `execute()`.
+fn arithmetic(instruction: &Instruction, a: Type, b: Type) -> Option<Type> {
+    match (instruction, a, b) {
+        (_, Type::Unknown, _) | (_, _, Type::Unknown) => Some(Type::Unknown),
+        (Instruction::Add(_), Type::String, Type::String) => Some(Type::String),
+        (Instruction::IDiv(_), a, b) if a.is_numeric() && b.is_numeric() => Some(Type::Integer),
+        (_, Type::Integer, Type::Integer) => Some(Type::Integer),
+        (_, a, b) if a.is_numeric() && b.is_numeric() => Some(Type::Float),
+        _ => None,
+    }
+}
+
+struct Checker<'a> {
+    program: &'a [Instruction],
+    labels: &'a HashMap<String, usize>,
+    errors: Vec<(usize, Error)>,
+    reported: HashSet<usize>,
+}
+
+impl<'a> Checker<'a> {
+    fn mismatch(&mut self, ip: usize, mnemonic: &str, types: &[Type]) {
+        if !self.reported.insert(ip) {
+            return;
+        }
+
+        let types: Vec<String> = types.iter().map(Type::to_string).collect();
+        let message = format!(
+            "Type mismatch for {} instruction: found {}",
+            mnemonic,
+            types.join(" and ")
+        );
+        let error = Error::new("E0106", &message, self.program[ip].pos());
+        self.errors.push((ip, error));
+    }
+
+    /// Applies the instruction at `state.ip` and returns the states that
+    /// can follow it.
+    fn step(&mut self, mut state: State) -> Vec<State> {
+        let ip = state.ip;
+        let pop = |stack: &mut Vec<Type>| stack.pop().unwrap_or(Type::Unknown);
+        state.ip += 1;
+
+        match &self.program[ip] {
+            Instruction::Push(value, _) => state.stack.push(Type::of(value)),
+            Instruction::Pop(_)
+            | Instruction::Print(_)
+            | Instruction::Assert(_)
+            | Instruction::Set(_, _) => {
+                pop(&mut state.stack);
+            }
+            Instruction::Duplicate(_) => {
+                let a = pop(&mut state.stack);
+                state.stack.push(a);
+                state.stack.push(a);
+            }
+            Instruction::Swap(_) => {
+                let a = pop(&mut state.stack);
+                let b = pop(&mut state.stack);
+                state.stack.push(a);
+                state.stack.push(b);
+            }
+            instruction @ (Instruction::Add(_)
+            | Instruction::Sub(_)
+            | Instruction::Mul(_)
+            | Instruction::Div(_)
+            | Instruction::IDiv(_)
+            | Instruction::Mod(_)) => {
+                let a = pop(&mut state.stack);
+                let b = pop(&mut state.stack);
+                match arithmetic(instruction, a, b) {
+                    Some(result) => state.stack.push(result),
+                    None => {
+                        let mnemonic = match instruction {
+                            Instruction::Add(_) => "ADD",
+                            Instruction::Sub(_) => "SUB",
+                            Instruction::Mul(_) => "MUL",
+                            Instruction::Div(_) => "DIV",
+                            Instruction::IDiv(_) => "IDIV",
+                            _ => "MOD",
+                        };
+                        self.mismatch(ip, mnemonic, &[b, a]);
+                        state.stack.push(Type::Unknown);
+                    }
+                }
+            }
+            Instruction::Cmp(value, _) => {
+                let a = pop(&mut state.stack);
+                let b = Type::of(value);
+                if a != Type::Unknown && a != b {
+                    self.mismatch(ip, "CMP", &[a, b]);
+                }
+                state.stack.push(a);
+            }
+            Instruction::CmpInStack(_) => {
+                let a = pop(&mut state.stack);
+                let b = pop(&mut state.stack);
+                if a != Type::Unknown && b != Type::Unknown && a != b {
+                    self.mismatch(ip, "SCMP", &[b, a]);
+                }
+                state.stack.push(b);
+                state.stack.push(a);
+            }
+            Instruction::Read(_) => state.stack.push(Type::String),
+            Instruction::AToI(_) => {
+                let a = pop(&mut state.stack);
+                if a != Type::Unknown && a != Type::String {
+                    self.mismatch(ip, "ATOI", &[a]);
+                }
+                state.stack.push(Type::Integer);
+            }
+            Instruction::FToI(_) => {
+                let a = pop(&mut state.stack);
+                if a != Type::Unknown && a != Type::Float {
+                    self.mismatch(ip, "FTOI", &[a]);
+                }
+                state.stack.push(Type::Integer);
+            }
+            Instruction::IToA(_) | Instruction::IToF(_) => {
+                pop(&mut state.stack);
+                state.stack.push(Type::Unknown);
+            }
+            Instruction::SeedRandom(_) => {
+                let a = pop(&mut state.stack);
+                if a != Type::Unknown && a != Type::Integer {
+                    self.mismatch(ip, "SRAND", &[a]);
+                }
+            }
+            Instruction::AssertEq(_, _) => {
+                pop(&mut state.stack);
+                pop(&mut state.stack);
+            }
+            Instruction::Random(_) => state.stack.push(Type::Float),
+            Instruction::RandomInt(_, _, _) => state.stack.push(Type::Integer),
+            Instruction::Time(_) | Instruction::Get(_, _) => state.stack.push(Type::Unknown),
+            Instruction::Label(_, _) => {}
+            Instruction::Exit(_) => return Vec::new(),
+            Instruction::EndTry(_) => {}
+            Instruction::Throw(_) => return Vec::new(),
+            Instruction::Try(label, _) => {
+                // The handler starts with the stack of the `try` and the
+                // error value on top.
+                let mut next = vec![state.clone()];
+                if let Some(target) = self.labels.get(label) {
+                    let mut caught = state;
+                    caught.stack.push(Type::Unknown);
+                    caught.ip = *target;
+                    next.push(caught);
+                }
+                return next;
+            }
+            Instruction::Return(_) => match state.return_stack.pop() {
+                Some(return_ip) => state.ip = return_ip,
+                None => return Vec::new(),
+            },
+            Instruction::Jump(label, _) | Instruction::Call(label, _, _, _) => {
+                return self.call(state, label, ip, false)
+            }
+            Instruction::JumpEq(label, _)
+            | Instruction::JumpNotEq(label, _)
+            | Instruction::JumpGt(label, _)
+            | Instruction::JumpLt(label, _)
+            | Instruction::JumpGtEq(label, _)
+            | Instruction::JumpLtEq(label, _)
+            | Instruction::JumpZero(label, _)
+            | Instruction::JumpNotZero(label, _)
+            | Instruction::JumpNeg(label, _) => return self.call(state, label, ip, true),
+        }
+
+        vec![state]
+    }
+
+    fn call(&self, state: State, label: &str, ip: usize, conditional: bool) -> Vec<State> {
+        let mut next = Vec::new();
+
+        if conditional {
+            next.push(state.clone());
+        }
+
+        if let Some(target) = self.labels.get(label) {
+            if state.return_stack.len() < MAX_CALL_DEPTH {
+                let mut taken = state;
+                taken.return_stack.push(ip + 1);
+                taken.ip = *target;
+                next.push(taken);
+            }
+        }
+
+        next
+    }
+}
+
+/// Infers the type of every stack slot along every path from the start of
+/// the program and returns the instructions that definitely receive operands
+/// of the wrong type, in program order.
+pub(crate) fn check(program: &[Instruction], labels: &HashMap<String, usize>) -> Vec<Error> {
+    let mut checker = Checker {
+        program,
+        labels,
+        errors: Vec::new(),
+        reported: HashSet::new(),
+    };
+
+    let mut visited: HashSet<State> = HashSet::new();
+    let mut work = vec![State {
+        ip: 0,
+        stack: Vec::new(),
+        return_stack: Vec::new(),
+    }];
+
+    while let Some(state) = work.pop() {
+        if state.ip >= program.len() || visited.len() >= MAX_STATES {
+            continue;
+        }
+        if !visited.insert(state.clone()) {
+            continue;
+        }
+
+        work.extend(checker.step(state));
+    }
+
+    let mut errors = checker.errors;
+    errors.sort_by_key(|(ip, _)| *ip);
+    errors.into_iter().map(|(_, error)| error).collect()
+}
@@ -0,0 +1,69 @@
+use crate::{
+    error::{Error, SourceMap, Span, DEFAULT_MAX_ERRORS},
+    evilstack_vm::EvilStackVM,
+    golden::TEST_SEED,
+    include,
+};
+use colored::Colorize;
+use std::{fs, path::Path};
+
+/// Labels with this prefix are run as unit tests by `estack-vm test`.
+pub const TEST_PREFIX: &str = "test_";
+
+fn load(program: &Path, code: &str) -> (SourceMap, Result<EvilStackVM, Vec<Error>>) {
+    let name = program.display().to_string();
+    let (sources, symbols, errors) = include::load(&name, code, DEFAULT_MAX_ERRORS);
+    if !errors.is_empty() {
+        return (sources, Err(errors));
+    }
+
+    (sources, Ok(EvilStackVM::new(symbols).with_seed(TEST_SEED)))
+}
+
+/// Returns the `test_` labels defined in `code`, read from `program`, with
+/// their positions, or an empty list if the program does not compile.
+pub fn test_labels(program: &Path, code: &str) -> Vec<(String, Span)> {
+    let labels = match load(program, code).1.and_then(|mut vm| vm.labels()) {
+        Ok(labels) => labels,
+        Err(_) => return Vec::new(),
+    };
+
+    labels
+        .into_iter()
+        .filter(|(label, _)| label.starts_with(TEST_PREFIX))
+        .collect()
+}
+
+/// Runs every `test_` label of `program` in a fresh VM. Returns the number
+/// of passed and failed tests.
+pub fn run(program: &Path) -> (usize, usize) {
+    let code = match fs::read_to_string(program) {
+        Ok(code) => code,
+        Err(_) => return (0, 0),
+    };
+
+    let mut passed = 0;
+    let mut failed = 0;
+
+    for (label, pos) in test_labels(program, &code) {
+        let (sources, vm) = load(program, &code);
+        let position = sources.position(pos);
+        print!("test {}::{} ({}) ... ", program.display(), label, position);
+
+        match vm.and_then(|mut vm| vm.execute_label(&label)) {
+            Ok(_) => {
+                println!("{}", "ok".green());
+                passed += 1;
+            }
+            Err(errors) => {
+                println!("{}", "FAILED".red());
+                for error in errors {
+                    print!("{}", sources.render(error));
+                }
+                failed += 1;
+            }
+        }
+    }
+
+    (passed, failed)
+}
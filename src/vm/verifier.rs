@@ -0,0 +1,337 @@
+//! Static stack-effect verifier.
+//!
+//! Every jump in EvilStack behaves like a call: the address after the jump is
+//! pushed on the return stack and `ret` comes back to it. The verifier
+//! therefore summarizes each jump target as a subroutine (how deep into the
+//! caller's stack it reaches and how much it leaves behind when it returns)
+//! and uses those summaries to follow the stack depth through the program.
+
+use crate::{
+    error::{Error, Span},
+    evilstack_vm::Instruction,
+};
+use std::collections::{HashMap, HashSet};
+
+/// How many times the subroutine summaries are recomputed before giving up on
+/// reaching a fixed point (recursive subroutines with a growing effect).
+const MAX_ITERATIONS: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+struct Summary {
+    /// Lowest stack depth reached, relative to the depth at entry.
+    min: i64,
+    /// Instruction at which `min` is first reached.
+    culprit: Option<usize>,
+    /// Net stack effect when the subroutine returns, `None` if it never does.
+    returns: Option<i64>,
+    /// Lowest depth reached before the first branch, which every call
+    /// reaches, and the instruction that reaches it.
+    sure_min: i64,
+    sure_culprit: Option<usize>,
+    /// Whether every path reaches the same depths, so the net effect does
+    /// not depend on the path taken.
+    exact: bool,
+}
+
+impl Summary {
+    fn new() -> Summary {
+        Summary {
+            min: 0,
+            culprit: None,
+            returns: None,
+            sure_min: 0,
+            sure_culprit: None,
+            exact: true,
+        }
+    }
+}
+
+enum Flow {
+    /// Pops and pushes values, then continues with the next instruction.
+    Next(i64, i64),
+    /// Calls the label, conditionally or not.
+    Call(String, bool),
+    /// Continues with the next instruction, or at the handler of a `try`
+    /// block with the error value pushed.
+    Try(String),
+    Return,
+    Stop,
+}
+
+fn flow(instruction: &Instruction) -> Flow {
+    match instruction {
+        Instruction::Push(_, _) | Instruction::Read(_) | Instruction::Get(_, _) => Flow::Next(0, 1),
+        Instruction::Random(_) | Instruction::RandomInt(_, _, _) | Instruction::Time(_) => {
+            Flow::Next(0, 1)
+        }
+        Instruction::Pop(_)
+        | Instruction::Print(_)
+        | Instruction::Assert(_)
+        | Instruction::Set(_, _)
+        | Instruction::SeedRandom(_) => Flow::Next(1, 0),
+        Instruction::Duplicate(_) => Flow::Next(1, 2),
+        Instruction::Swap(_) | Instruction::CmpInStack(_) => Flow::Next(2, 2),
+        Instruction::Add(_)
+        | Instruction::Sub(_)
+        | Instruction::Mul(_)
+        | Instruction::Div(_)
+        | Instruction::IDiv(_)
+        | Instruction::Mod(_) => Flow::Next(2, 1),
+        Instruction::AssertEq(_, _) => Flow::Next(2, 0),
+        Instruction::Cmp(_, _)
+        | Instruction::AToI(_)
+        | Instruction::IToA(_)
+        | Instruction::IToF(_)
+        | Instruction::FToI(_) => Flow::Next(1, 1),
+        Instruction::Label(_, _) => Flow::Next(0, 0),
+        Instruction::Jump(label, _) | Instruction::Call(label, _, _, _) => {
+            Flow::Call(label.clone(), false)
+        }
+        Instruction::JumpEq(label, _)
+        | Instruction::JumpNotEq(label, _)
+        | Instruction::JumpGt(label, _)
+        | Instruction::JumpLt(label, _)
+        | Instruction::JumpGtEq(label, _)
+        | Instruction::JumpLtEq(label, _)
+        | Instruction::JumpZero(label, _)
+        | Instruction::JumpNotZero(label, _)
+        | Instruction::JumpNeg(label, _) => Flow::Call(label.clone(), true),
+        Instruction::Return(_) => Flow::Return,
+        Instruction::Exit(_) | Instruction::Throw(_) => Flow::Stop,
+        Instruction::EndTry(_) => Flow::Next(0, 0),
+        Instruction::Try(label, _) => Flow::Try(label.clone()),
+    }
+}
+
+struct Verifier<'a> {
+    program: &'a [Instruction],
+    labels: &'a HashMap<String, usize>,
+    summaries: HashMap<String, Summary>,
+    diagnostics: Vec<(usize, Error)>,
+    reported: HashSet<(&'static str, usize)>,
+}
+
+impl<'a> Verifier<'a> {
+    fn report(&mut self, ip: usize, error: Error) {
+        if self.reported.insert((error.code, ip)) {
+            self.diagnostics.push((ip, error));
+        }
+    }
+
+    fn pos(&self, ip: usize) -> Span {
+        self.program[ip].pos()
+    }
+
+    /// Reports an underflow at the top level: an error if the code is
+    /// `sure` to run with that depth, a warning if only some paths do.
+    fn underflow(&mut self, ip: usize, sure: bool, message: &str) -> Error {
+        match sure {
+            true => Error::new(
+                "E0105",
+                &format!("Stack underflow: {}", message),
+                self.pos(ip),
+            ),
+            false => Error::warning(
+                "W0103",
+                &format!("Possible stack underflow: {}", message),
+                self.pos(ip),
+            )
+            .with_help("this happens only on some paths through the program"),
+        }
+    }
+
+    /// Follows the stack depth from `start`. With `label`, the code is
+    /// analyzed as that subroutine and depths are relative to its entry;
+    /// without it, as the top level, where a negative depth is an underflow.
+    /// Code is `sure` to run with its depth until the first branch.
+    fn analyze(&mut self, start: usize, label: Option<&str>, report: bool) -> Summary {
+        let mut summary = Summary::new();
+        let mut depths: Vec<Option<i64>> = vec![None; self.program.len()];
+        let mut work = vec![(start, 0, None, true)];
+
+        while let Some((ip, depth, from, sure)) = work.pop() {
+            if ip >= self.program.len() {
+                continue;
+            }
+
+            if let Some(seen) = depths[ip] {
+                if seen != depth {
+                    summary.exact = false;
+                }
+                if seen != depth && report {
+                    let message = match label {
+                        Some(label) => format!(
+                            "Inconsistent stack depth: reached with {:+} and {:+} values relative to the start of `{}`",
+                            seen, depth, label
+                        ),
+                        None => format!(
+                            "Inconsistent stack depth: reached with {} and {} values on the stack",
+                            seen, depth
+                        ),
+                    };
+                    let mut warning = Error::warning("W0101", &message, self.pos(ip));
+                    if let Some(from) = from {
+                        warning = warning.with_note(
+                            &format!("the path with {} values comes from here", depth),
+                            self.pos(from),
+                        );
+                    }
+                    self.report(ip, warning);
+                }
+                continue;
+            }
+            depths[ip] = Some(depth);
+
+            match flow(&self.program[ip]) {
+                Flow::Next(pops, pushes) => {
+                    let mut low = depth - pops;
+                    if low < summary.min {
+                        summary.min = low;
+                        summary.culprit = Some(ip);
+                    }
+                    if sure && low < summary.sure_min {
+                        summary.sure_min = low;
+                        summary.sure_culprit = Some(ip);
+                    }
+                    if low < 0 && label.is_none() {
+                        if report {
+                            let message = format!(
+                                "this instruction needs {} value{} but the stack holds {}",
+                                pops,
+                                if pops == 1 { "" } else { "s" },
+                                depth
+                            );
+                            let error = self.underflow(ip, sure, &message);
+                            self.report(ip, error);
+                        }
+                        low = 0;
+                    }
+                    work.push((ip + 1, low + pushes, Some(ip), sure));
+                }
+                Flow::Call(target, conditional) => {
+                    if conditional {
+                        work.push((ip + 1, depth, Some(ip), false));
+                    }
+
+                    let callee = self
+                        .summaries
+                        .get(&target)
+                        .cloned()
+                        .unwrap_or(Summary::new());
+                    let sure = sure && !conditional;
+                    let low = depth + callee.min;
+                    if low < summary.min {
+                        summary.min = low;
+                        summary.culprit = callee.culprit.or(Some(ip));
+                    }
+                    let sure_low = depth + callee.sure_min;
+                    if sure && sure_low < summary.sure_min {
+                        summary.sure_min = sure_low;
+                        summary.sure_culprit = callee.sure_culprit.or(Some(ip));
+                    }
+                    if !callee.exact {
+                        summary.exact = false;
+                    }
+                    if low < 0 && label.is_none() && report {
+                        let (low, culprit, sure) = match sure && sure_low < 0 {
+                            true => (sure_low, callee.sure_culprit, true),
+                            false => (low, callee.culprit, false),
+                        };
+                        let culprit = culprit.unwrap_or(ip);
+                        let message = format!(
+                            "`{}` needs {} more value{} than the stack holds",
+                            target,
+                            -low,
+                            if low == -1 { "" } else { "s" }
+                        );
+                        let error = self
+                            .underflow(culprit, sure, &message)
+                            .with_note("when called from here", self.pos(ip));
+                        self.report(culprit, error);
+                    }
+
+                    if let Some(net) = callee.returns {
+                        let depth = match label {
+                            Some(_) => depth + net,
+                            None => (depth + net).max(0),
+                        };
+                        work.push((ip + 1, depth, Some(ip), sure && callee.exact));
+                    }
+                }
+                Flow::Try(handler) => {
+                    work.push((ip + 1, depth, Some(ip), false));
+                    if let Some(target) = self.labels.get(&handler) {
+                        work.push((*target, depth + 1, Some(ip), false));
+                    }
+                }
+                Flow::Return => match (label, summary.returns) {
+                    (None, _) => {}
+                    (Some(_), None) => summary.returns = Some(depth),
+                    (Some(label), Some(returns)) if returns != depth => {
+                        summary.exact = false;
+                        if !report {
+                            continue;
+                        }
+                        let message = format!(
+                            "Unbalanced subroutine: `{}` returns with {:+} and {:+} values on the stack",
+                            label, returns, depth
+                        );
+                        let warning = Error::warning("W0102", &message, self.pos(ip));
+                        self.report(ip, warning);
+                    }
+                    _ => {}
+                },
+                Flow::Stop => {}
+            }
+        }
+
+        summary
+    }
+}
+
+/// Checks the stack effects of `program`. Guaranteed underflows are
+/// returned as errors; underflows on some paths, inconsistent depths and
+/// unbalanced subroutines as warnings, in program order.
+pub(crate) fn verify(program: &[Instruction], labels: &HashMap<String, usize>) -> Vec<Error> {
+    let mut verifier = Verifier {
+        program,
+        labels,
+        summaries: HashMap::new(),
+        diagnostics: Vec::new(),
+        reported: HashSet::new(),
+    };
+
+    // Handlers of `try` blocks are part of the code around them rather than
+    // subroutines.
+    let mut targets: Vec<(String, usize)> = program
+        .iter()
+        .filter(|instruction| !matches!(instruction, Instruction::Try(_, _)))
+        .filter_map(|instruction| instruction.target())
+        .filter_map(|label| labels.get(label).map(|ip| (label.to_string(), *ip)))
+        .collect();
+    targets.sort();
+    targets.dedup();
+
+    for _ in 0..MAX_ITERATIONS {
+        let mut changed = false;
+        for (label, ip) in &targets {
+            let summary = verifier.analyze(*ip, Some(label), false);
+            if verifier.summaries.get(label) != Some(&summary) {
+                verifier.summaries.insert(label.clone(), summary);
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    for (label, ip) in &targets {
+        verifier.analyze(*ip, Some(label), true);
+    }
+    verifier.analyze(0, None, true);
+
+    let mut diagnostics = verifier.diagnostics;
+    diagnostics.sort_by_key(|(ip, _)| *ip);
+    diagnostics.into_iter().map(|(_, error)| error).collect()
+}
@@ -0,0 +1,49 @@
+//! Helpers shared by the integration tests that run `estack-vm` on programs
+//! written to a temporary directory.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{self, Command, Output};
+
+/// A directory under the system's temporary directory, removed with its
+/// contents when dropped, even if the test fails first.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// Creates `estack-<name>-<pid>` in the temporary directory.
+    pub fn new(name: &str) -> TempDir {
+        let path = env::temp_dir().join(format!("estack-{}-{}", name, process::id()));
+        fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+
+    /// The path of the file `name` in the directory.
+    pub fn file(&self, name: &str) -> String {
+        self.path.join(name).to_str().unwrap().to_string()
+    }
+
+    /// Writes `contents` to the file `name` in the directory and returns its
+    /// path.
+    pub fn write(&self, name: &str, contents: &str) -> String {
+        let path = self.file(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Runs `estack-vm` with `args`.
+pub fn estack_vm(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_estack-vm"))
+        .args(args)
+        .output()
+        .expect("Failed to run estack-vm")
+}
@@ -0,0 +1,22 @@
+use std::process::Command;
+
+fn run_golden_tests(dir: &str) {
+    let output = Command::new(env!("CARGO_BIN_EXE_estack-vm"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .env("NO_COLOR", "1")
+        .arg("test")
+        .arg(dir)
+        .output()
+        .expect("Failed to run estack-vm");
+
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+}
+
+#[test]
+fn golden_tests() {
+    run_golden_tests("tests");
+}
@@ -0,0 +1,138 @@
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{ChildStdout, Command, Stdio};
+
+const PROGRAM: &str = "push 1\njmp @double\nprint\nexit\n\ndouble:\n  dup\n  add\n  ret\n";
+
+fn frame(message: Value) -> String {
+    let body = message.to_string();
+    format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+}
+
+fn read_message(output: &mut BufReader<ChildStdout>) -> Value {
+    let mut length = 0;
+    loop {
+        let mut header = String::new();
+        output.read_line(&mut header).unwrap();
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse().unwrap();
+        }
+    }
+
+    let mut body = vec![0; length];
+    output.read_exact(&mut body).unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
+
+fn request(id: u64, method: &str, params: Value) -> String {
+    frame(json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }))
+}
+
+fn notification(method: &str, params: Value) -> String {
+    frame(json!({ "jsonrpc": "2.0", "method": method, "params": params }))
+}
+
+fn position(line: u64, character: u64) -> Value {
+    json!({
+        "textDocument": { "uri": "file:///double.estk" },
+        "position": { "line": line, "character": character },
+        "context": { "includeDeclaration": true },
+    })
+}
+
+#[test]
+fn language_server_session() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_estack-lsp"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to run estack-lsp");
+
+    let document = json!({ "uri": "file:///double.estk", "languageId": "estack", "version": 1, "text": PROGRAM });
+    let broken = json!({
+        "textDocument": { "uri": "file:///double.estk", "version": 2 },
+        "contentChanges": [{ "text": "push 1\njmp @doubel\n" }],
+    });
+
+    let mut input = String::new();
+    input += &request(1, "initialize", json!({ "capabilities": {} }));
+    input += &notification("initialized", json!({}));
+    input += &notification("textDocument/didOpen", json!({ "textDocument": document }));
+    input += &request(2, "textDocument/definition", position(1, 6));
+    input += &request(3, "textDocument/references", position(5, 2));
+    input += &request(4, "textDocument/hover", position(6, 3));
+    input += &request(5, "textDocument/completion", position(2, 0));
+    input += &request(6, "textDocument/documentSymbol", position(0, 0));
+    input += &request(7, "textDocument/semanticTokens/full", position(0, 0));
+    input += &notification("textDocument/didChange", broken);
+    input += &request(8, "shutdown", Value::Null);
+    input += &notification("exit", Value::Null);
+
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let mut output = BufReader::new(child.stdout.take().unwrap());
+
+    let initialize = read_message(&mut output);
+    assert_eq!(
+        initialize["result"]["capabilities"]["definitionProvider"],
+        true
+    );
+
+    let diagnostics = read_message(&mut output);
+    assert_eq!(diagnostics["method"], "textDocument/publishDiagnostics");
+    assert_eq!(diagnostics["params"]["diagnostics"], json!([]));
+
+    let definition = read_message(&mut output);
+    assert_eq!(
+        definition["result"]["range"]["start"],
+        json!({ "line": 5, "character": 0 })
+    );
+
+    let references = read_message(&mut output);
+    assert_eq!(references["result"].as_array().unwrap().len(), 2);
+
+    let hover = read_message(&mut output);
+    let hover = hover["result"]["contents"]["value"].as_str().unwrap();
+    assert!(hover.contains("Duplicates the top value"), "{}", hover);
+
+    let completion = read_message(&mut output);
+    let labels: Vec<&str> = completion["result"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| item["label"].as_str().unwrap())
+        .collect();
+    assert!(labels.contains(&"push"));
+    assert!(labels.contains(&"@double"));
+
+    let symbols = read_message(&mut output);
+    assert_eq!(symbols["result"][0]["name"], "double");
+
+    // `push 1` on the first line: a keyword, then a number 5 columns later.
+    let tokens = read_message(&mut output);
+    assert_eq!(
+        tokens["result"]["data"].as_array().unwrap()[..10],
+        json!([0, 0, 4, 0, 0, 0, 5, 1, 2, 0]).as_array().unwrap()[..]
+    );
+
+    let diagnostics = read_message(&mut output);
+    let diagnostics = diagnostics["params"]["diagnostics"].as_array().unwrap();
+    assert_eq!(diagnostics[0]["code"], "E0104");
+    assert_eq!(
+        diagnostics[0]["range"]["start"],
+        json!({ "line": 1, "character": 4 })
+    );
+
+    let shutdown = read_message(&mut output);
+    assert_eq!(shutdown["id"], 8);
+
+    assert!(child.wait().unwrap().success());
+}
@@ -0,0 +1,89 @@
+mod common;
+
+use common::{estack_vm, TempDir};
+use evilstack_lang::{evilstack_vm::EvilStackVM, include, limits::Limits, snapshot::Snapshot};
+use std::fs;
+
+const PROGRAM: &str = "push 0\nset r1\npush 0\ncall @count 1 1\nprint\nget r1\nprint\nexit\n\ncount:\n  push 1\n  add\n  get r1\n  push 2\n  add\n  set r1\n  cmp 300\n  jlt @count\n  ret\n";
+
+#[test]
+fn resumed_run_finishes_like_an_uninterrupted_one() {
+    let dir = TempDir::new("snapshot");
+    let program = dir.write("count.estk", PROGRAM);
+    let first = dir.file("first.json");
+    let second = dir.file("second.json");
+
+    let uninterrupted = estack_vm(&[&program]);
+    assert!(uninterrupted.status.success());
+    assert_eq!(String::from_utf8_lossy(&uninterrupted.stdout), "300\n600\n");
+
+    let stopped = estack_vm(&[
+        "--limit",
+        "instructions=1000",
+        "--snapshot-on-exit",
+        &first,
+        &program,
+    ]);
+    assert!(!stopped.status.success());
+    assert!(String::from_utf8_lossy(&stopped.stderr).contains("E0215"));
+
+    // The program file is not needed any more.
+    fs::remove_file(&program).unwrap();
+    let resumed = estack_vm(&["--resume", &first, "--snapshot-on-exit", &second]);
+    assert!(resumed.status.success());
+    assert_eq!(resumed.stdout, uninterrupted.stdout);
+
+    // A run resumed after `exit` ends right away.
+    let finished = estack_vm(&["--resume", &second]);
+    assert!(finished.status.success());
+    assert!(finished.stdout.is_empty());
+}
+
+#[test]
+fn snapshot_restores_through_json() {
+    let (sources, symbols, errors) = include::load("count.estk", PROGRAM, 20);
+    assert!(errors.is_empty());
+
+    let mut limits = Limits::default();
+    limits.set("instructions=100").unwrap();
+    let mut vm = EvilStackVM::new(symbols).with_limits(limits);
+    let errors = vm.execute().unwrap_err();
+    assert_eq!(errors[0].code, "E0215");
+
+    let json = vm.snapshot(&sources).to_json();
+    let snapshot = Snapshot::from_json(&json).unwrap();
+    assert_eq!(snapshot.sources().get(0).unwrap().source, PROGRAM);
+
+    let mut resumed = EvilStackVM::new(snapshot.symbols().to_vec());
+    resumed.restore(&snapshot).unwrap();
+    // Only the seed differs, as taking a snapshot reseeds the generator.
+    let without_seed = |json: &str| {
+        let mut state: serde_json::Value = serde_json::from_str(json).unwrap();
+        state.as_object_mut().unwrap().remove("seed");
+        state
+    };
+    let again = resumed.snapshot(&sources).to_json();
+    assert_eq!(without_seed(&again), without_seed(&json));
+    assert!(resumed.execute().is_ok());
+
+    assert!(Snapshot::from_json("{\"version\": 2}").is_err());
+}
+
+#[test]
+fn restore_rejects_a_call_that_returns_outside_the_program() {
+    let (sources, symbols, _) = include::load("count.estk", PROGRAM, 20);
+    let mut limits = Limits::default();
+    limits.set("instructions=100").unwrap();
+    let mut vm = EvilStackVM::new(symbols).with_limits(limits);
+    assert!(vm.execute().is_err());
+
+    let mut json: serde_json::Value =
+        serde_json::from_str(&vm.snapshot(&sources).to_json()).unwrap();
+    json["calls"][0]["return_address"] = 0.into();
+    let snapshot = Snapshot::from_json(&json.to_string()).unwrap();
+
+    let mut resumed = EvilStackVM::new(snapshot.symbols().to_vec());
+    let errors = resumed.restore(&snapshot).unwrap_err();
+    assert_eq!(errors[0].code, "E0217");
+    assert!(errors[0].message.contains("return address 0"));
+}
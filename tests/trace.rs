@@ -0,0 +1,48 @@
+mod common;
+
+use common::{estack_vm, TempDir};
+use std::fs;
+
+const PROGRAM: &str =
+    "push \"text\"\npush -7\npush 2.5\nswap\nmul\ndup\nset x\nget x\nadd\nprint\nprint\nexit\n";
+
+#[test]
+fn binary_trace_replays_as_text() {
+    let dir = TempDir::new("trace");
+    let program = dir.write("program.estk", PROGRAM);
+    let binary = dir.file("trace.bin");
+
+    let text = estack_vm(&["--trace", &program]);
+    assert!(text.status.success());
+
+    let traced = estack_vm(&["--trace-file", &binary, "--trace-format=binary", &program]);
+    assert!(traced.status.success());
+    assert!(fs::metadata(&binary).unwrap().len() < text.stderr.len() as u64);
+
+    let replayed = estack_vm(&["replay", &binary]);
+    assert!(replayed.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&replayed.stdout),
+        String::from_utf8_lossy(&text.stderr)
+    );
+}
+
+#[test]
+fn replay_rejects_lengths_beyond_the_input() {
+    let dir = TempDir::new("trace-corrupt");
+    let program = dir.write("program.estk", PROGRAM);
+    let binary = dir.file("trace.bin");
+    let traced = estack_vm(&["--trace-file", &binary, "--trace-format=binary", &program]);
+    assert!(traced.status.success());
+    let magic = fs::read(&binary).unwrap()[..8].to_vec();
+
+    // One file whose name claims 2^62 bytes.
+    let huge_name = [&magic[..], &[1], &[0x80; 8], &[0x40]].concat();
+    // 2^62 files.
+    let huge_table = [&magic[..], &[0x80; 8], &[0x40]].concat();
+    for corrupt in [huge_name, huge_table] {
+        fs::write(&binary, corrupt).unwrap();
+        let replayed = estack_vm(&["replay", &binary]);
+        assert!(!replayed.status.success());
+    }
+}